@@ -1,8 +1,8 @@
-use reqwest::header::{HeaderMap, CONTENT_TYPE};
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
 use serde::Deserialize;
 use std::collections::HashMap;
 
-const API_TOKEN_URL: &str = "https://prod.zh.igpsport.com/service/auth/connect/token";
+pub const API_TOKEN_URL: &str = "https://prod.zh.igpsport.com/service/auth/connect/token";
 
 #[derive(Deserialize, Debug)]
 pub struct AuthResponse {
@@ -11,7 +11,12 @@ pub struct AuthResponse {
     pub access_token: Option<String>,
 }
 
-pub async fn get_token(username: &str, password: &str) -> AuthResponse {
+/// Log in with username and password, a rejected login is an `Ok` without `access_token`.
+pub async fn get_token(
+    token_url: &str,
+    username: &str,
+    password: &str,
+) -> Result<AuthResponse, reqwest::Error> {
     let mut params = HashMap::new();
     params.insert("username", username);
     params.insert("password", password);
@@ -22,21 +27,19 @@ pub async fn get_token(username: &str, password: &str) -> AuthResponse {
         "openid offline_access mobile.api user.api device.api activity.api IdentityServerApi",
     );
     params.insert("client_id", "qiwu.mobile");
-    let client = reqwest::Client::builder().build().unwrap();
+    let client = reqwest::Client::builder().build()?;
     let mut headers = HeaderMap::new();
     headers.insert(
         CONTENT_TYPE,
-        "application/x-www-form-urlencoded".parse().unwrap(),
+        HeaderValue::from_static("application/x-www-form-urlencoded"),
     );
 
     client
-        .post(API_TOKEN_URL)
+        .post(token_url)
         .headers(headers)
         .form(&params)
         .send()
-        .await
-        .unwrap()
+        .await?
         .json::<AuthResponse>()
         .await
-        .unwrap()
 }
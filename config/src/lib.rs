@@ -0,0 +1,219 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+/// Points to another config file than the default one
+pub const CONFIG_ENV: &str = "IGPS_TOOLS_CONFIG";
+
+/// Shared config of `workout` and `xingzhe`, read from `~/.config/igps_tools/config.toml`
+///
+/// ```toml
+/// [igps]
+/// token = "Bearer xxx"
+///
+/// [workout]
+/// ftp = 250
+/// adjustment = { operation = "add", target_type = "power", target_value = 10 }
+///
+/// [profiles.alice]
+/// token = "Bearer xxx"
+/// ftp = 230
+///
+/// [xingzhe]
+/// cookie = "xxx"
+/// user_id = 123
+/// output_dir = "./xingzhe"
+/// filename = "{date} {title}"
+/// cache_dir = "./xingzhe/raw"
+/// ftp = 250
+/// lap_distance = 10
+/// ```
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub igps: IGPSConfig,
+    pub workout: WorkoutConfig,
+    pub xingzhe: XingZheConfig,
+    /// Athletes selected with `workout --profile`, by name
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct IGPSConfig {
+    /// iGPS user token, including the `Bearer ` prefix
+    pub token: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Overrides the login endpoint
+    pub token_url: Option<String>,
+    /// Overrides the `EditCustomWorkOut` endpoint
+    pub workout_url: Option<String>,
+}
+
+/// One iGPS account and the FTP of its athlete
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    /// iGPS user token, including the `Bearer ` prefix
+    pub token: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// The power targets are scaled to this FTP
+    pub ftp: Option<u32>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkoutConfig {
+    /// The FTP the power targets of the FIT files are planned with
+    pub ftp: Option<u32>,
+    /// Where generated files (e.g. images) are written
+    pub output_dir: Option<PathBuf>,
+    /// Applied when no adjustment is given on the command line
+    pub adjustment: Option<Adjustment>,
+    /// Step name template, e.g. `"{name} {notes}"`
+    pub step_name: Option<String>,
+}
+
+/// Same as `workout <OPERATION> <TARGET_TYPE> -v <TARGET_VALUE>`
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Adjustment {
+    /// "add", "subtract", "multiply" or "divide"
+    pub operation: String,
+    /// "power" or "duration"
+    pub target_type: String,
+    pub target_value: u32,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct XingZheConfig {
+    /// XingZhe user cookie
+    pub cookie: Option<String>,
+    /// XingZhe user id
+    pub user_id: Option<u64>,
+    /// Overrides `https://www.imxingzhe.com`
+    pub base_url: Option<String>,
+    /// Where the FIT files are written
+    pub output_dir: Option<PathBuf>,
+    /// File name template, e.g. `"{date} {title}"`
+    pub filename: Option<String>,
+    /// Where the raw XingZhe responses are kept, `raw` in the output directory by default
+    pub cache_dir: Option<PathBuf>,
+    /// FTP of the athlete, for IF and TSS of the exported workouts
+    pub ftp: Option<u32>,
+    /// Start a new lap every so many kilometers
+    pub lap_distance: Option<f64>,
+    /// Start a new lap every so many minutes
+    pub lap_time: Option<u32>,
+}
+
+impl Config {
+    /// Load `path`, `$IGPS_TOOLS_CONFIG` or the default config file, in that order.
+    ///
+    /// A missing default file isn't an error, every value is optional.
+    pub fn load(path: Option<&Path>) -> Result<Config, Box<dyn Error>> {
+        let explicit = path
+            .map(Path::to_path_buf)
+            .or_else(|| env::var_os(CONFIG_ENV).map(PathBuf::from));
+        let path = match explicit {
+            Some(p) => p,
+            None => match default_path() {
+                Some(p) if p.is_file() => p,
+                _ => return Ok(Config::default()),
+            },
+        };
+        let content =
+            fs::read_to_string(&path).map_err(|e| format!("read config {:?} fail: {e}", path))?;
+        let config =
+            toml::from_str(&content).map_err(|e| format!("parse config {:?} fail: {e}", path))?;
+        Ok(config)
+    }
+}
+
+/// `~/.config/igps_tools/config.toml`
+pub fn default_path() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".config").join("igps_tools").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// The tests run in parallel, but `IGPS_TOOLS_CONFIG` is shared by the whole process
+    static ENV: Mutex<()> = Mutex::new(());
+
+    /// Write `content` to a config file only this test uses
+    fn config_file(name: &str, content: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("igps_tools_{}_{name}.toml", std::process::id()));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn load_the_given_file() {
+        let path = config_file(
+            "given",
+            r#"
+            [igps]
+            token = "Bearer file"
+
+            [workout]
+            ftp = 250
+            adjustment = { operation = "add", target_type = "power", target_value = 10 }
+
+            [profiles.alice]
+            username = "alice"
+            password = "secret"
+            ftp = 230
+            "#,
+        );
+        let config = Config::load(Some(&path)).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(config.igps.token.as_deref(), Some("Bearer file"));
+        assert_eq!(config.workout.ftp, Some(250));
+        assert_eq!(config.workout.adjustment.unwrap().target_value, 10);
+        assert_eq!(config.profiles["alice"].ftp, Some(230));
+        assert_eq!(config.xingzhe.cookie, None);
+    }
+
+    #[test]
+    fn unknown_fields_are_an_error() {
+        let path = config_file("unknown", "[igps]\ntokn = \"Bearer file\"\n");
+        let err = Config::load(Some(&path)).unwrap_err().to_string();
+        fs::remove_file(&path).unwrap();
+        assert!(err.contains("unknown field `tokn`"), "{err}");
+
+        let path = config_file("unknown_table", "[igpsport]\ntoken = \"x\"\n");
+        let err = Config::load(Some(&path)).unwrap_err().to_string();
+        fs::remove_file(&path).unwrap();
+        assert!(err.contains("unknown field `igpsport`"), "{err}");
+    }
+
+    #[test]
+    fn a_missing_file_is_an_error() {
+        let path = env::temp_dir().join("igps_tools_does_not_exist.toml");
+        assert!(Config::load(Some(&path)).is_err());
+    }
+
+    #[test]
+    fn the_environment_points_to_the_file() {
+        let _env = ENV.lock().unwrap();
+        let from_env = config_file("env", "[workout]\nftp = 200\n");
+        let given = config_file("given_over_env", "[workout]\nftp = 300\n");
+        env::set_var(CONFIG_ENV, &from_env);
+        let config = Config::load(None);
+        let explicit = Config::load(Some(&given));
+        env::remove_var(CONFIG_ENV);
+        fs::remove_file(&from_env).unwrap();
+        fs::remove_file(&given).unwrap();
+        assert_eq!(config.unwrap().workout.ftp, Some(200));
+        // the path on the command line wins
+        assert_eq!(explicit.unwrap().workout.ftp, Some(300));
+    }
+}
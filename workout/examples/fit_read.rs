@@ -1,6 +1,7 @@
 use fit_rust::protocol::data_field::DataField;
 use fit_rust::protocol::message_type::MessageType;
 use fit_rust::protocol::value::Value;
+use fit_rust::protocol::FitMessage;
 use fit_rust::Fit;
 use std::fs;
 
@@ -8,18 +9,22 @@ fn main() {
     let file = fs::read("examples/W2_5_125.fit").unwrap();
     let fit: Fit = Fit::read(file).unwrap();
     for data in &fit.data {
-        match data.message.message_type {
+        let msg = match data {
+            FitMessage::Definition(_) => continue,
+            FitMessage::Data(msg) => msg,
+        };
+        match msg.data.message_type {
             MessageType::FileId => {
-                println!("FileId: {:?}", data.message);
+                println!("FileId: {:?}", msg.data);
             }
             MessageType::Workout => {
-                print_workout(&data.message.values);
+                print_workout(&msg.data.values);
             }
             MessageType::WorkoutStep => {
-                print_workout_step(&data.message.values);
+                print_workout_step(&msg.data.values);
             }
             _ => {
-                println!("_: {:?}", data.message);
+                println!("_: {:?}", msg.data);
             }
         }
     }
@@ -1,17 +1,24 @@
-pub(crate) mod utils;
+pub mod utils;
 
-use reqwest::header::{HeaderMap, AUTHORIZATION, CONTENT_TYPE};
-use reqwest::Response;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use reqwest::{Response, StatusCode};
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
 
+/// The body of `EditCustomWorkOut`
 #[derive(Serialize, Deserialize, Debug)]
 pub struct IGPSRequestBody {
-    pub(crate) data: WorkoutData,
+    pub data: WorkoutData,
 }
 
+/// An iGPS custom workout
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
 pub struct WorkoutData {
     /// "workoutType": "bike"
+    /// "workoutType": "run"
+    /// "workoutType": "swim"
     #[serde(rename = "workoutType")]
     pub workout_type: String,
 
@@ -50,6 +57,230 @@ impl Default for WorkoutData {
     }
 }
 
+/// Targets above this are taken for a conversion error rather than a sprint.
+const MAX_WATTS: u32 = 2000;
+
+/// Longer titles are only warned about, iGPS doesn't document a limit and may cut them off
+const MAX_TITLE_LEN: usize = 50;
+
+impl WorkoutData {
+    /// Check the workout before it's sent to iGPS, every problem found is reported.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let mut problems = Vec::new();
+        if self.title.trim().is_empty() {
+            problems.push(ValidationProblem::EmptyTitle);
+        }
+        if self.title.chars().any(|c| c.is_control()) {
+            problems.push(ValidationProblem::TitleControlCharacters(
+                self.title.clone(),
+            ));
+        }
+        if self.structure.is_empty() {
+            problems.push(ValidationProblem::NoSteps);
+        }
+        let mut index = 0;
+        validate_structure(&self.structure, &mut index, &mut problems);
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError { problems })
+        }
+    }
+
+    /// What may go wrong in iGPS although the workout is valid
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let title_len = self.title.chars().count();
+        if title_len > MAX_TITLE_LEN {
+            warnings.push(format!(
+                "the title has {title_len} characters, iGPS may cut it off after {MAX_TITLE_LEN}"
+            ));
+        }
+        warnings
+    }
+}
+
+/// Every problem [`WorkoutData::validate`] found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    pub problems: Vec<ValidationProblem>,
+}
+
+/// One reason iGPS won't take a workout. `step` numbers the steps and repetitions from 1 in
+/// the order they're listed, repetitions not expanded; `name` is the step name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationProblem {
+    /// The title is empty or only whitespace
+    EmptyTitle,
+    /// The title holds a line break or another control character
+    TitleControlCharacters(String),
+    /// The workout has no steps
+    NoSteps,
+    /// A step lasts 0 seconds or meters
+    ZeroDuration { step: usize, name: String },
+    /// A repetition repeats its steps 0 times
+    ZeroRepetitions { step: usize, name: String },
+    /// A repetition has no steps to repeat
+    EmptyRepetition { step: usize, name: String },
+    /// The min pace, seconds per km or 100 m, is slower than the max pace
+    PaceRange {
+        step: usize,
+        name: String,
+        min: u32,
+        max: u32,
+    },
+    /// The min power is above the max power
+    PowerRange {
+        step: usize,
+        name: String,
+        min: u32,
+        max: u32,
+    },
+    /// A power target above [`MAX_WATTS`], most likely a conversion error
+    Watts {
+        step: usize,
+        name: String,
+        watts: u32,
+    },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, problem) in self.problems.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{problem}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// `step 3 (on)`, or `step 3` for a step without name
+struct StepLabel<'a>(usize, &'a str);
+
+impl fmt::Display for StepLabel<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.1 {
+            "" => write!(f, "step {}", self.0),
+            name => write!(f, "step {} ({name})", self.0),
+        }
+    }
+}
+
+impl fmt::Display for ValidationProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationProblem::EmptyTitle => write!(f, "the title is empty"),
+            ValidationProblem::TitleControlCharacters(title) => {
+                write!(f, "the title {title:?} contains control characters")
+            }
+            ValidationProblem::NoSteps => write!(f, "the workout has no steps"),
+            ValidationProblem::ZeroDuration { step, name } => {
+                write!(f, "{}: the duration is 0", StepLabel(*step, name))
+            }
+            ValidationProblem::ZeroRepetitions { step, name } => {
+                write!(f, "{}: repeats 0 times", StepLabel(*step, name))
+            }
+            ValidationProblem::EmptyRepetition { step, name } => {
+                write!(f, "{}: repeats no steps", StepLabel(*step, name))
+            }
+            ValidationProblem::PaceRange {
+                step,
+                name,
+                min,
+                max,
+            } => write!(
+                f,
+                "{}: min pace {min}s > max pace {max}s",
+                StepLabel(*step, name)
+            ),
+            ValidationProblem::PowerRange {
+                step,
+                name,
+                min,
+                max,
+            } => write!(
+                f,
+                "{}: min power {min}W > max power {max}W",
+                StepLabel(*step, name)
+            ),
+            ValidationProblem::Watts { step, name, watts } => write!(
+                f,
+                "{}: {watts}W is more than {MAX_WATTS}W",
+                StepLabel(*step, name)
+            ),
+        }
+    }
+}
+
+fn validate_structure(
+    structure: &[WorkoutDataStructure],
+    index: &mut usize,
+    problems: &mut Vec<ValidationProblem>,
+) {
+    for item in structure {
+        *index += 1;
+        let step = *index;
+        let name = || item.name.clone();
+        match &item.steps {
+            Some(steps) => {
+                if item.length.value == 0 {
+                    problems.push(ValidationProblem::ZeroRepetitions { step, name: name() });
+                }
+                if steps.is_empty() {
+                    problems.push(ValidationProblem::EmptyRepetition { step, name: name() });
+                }
+                validate_structure(steps, index, problems);
+            }
+            None => {
+                if item.length.value == 0 {
+                    problems.push(ValidationProblem::ZeroDuration { step, name: name() });
+                }
+                let Some(target) = &item.intensity_target else {
+                    continue;
+                };
+                if target.unit == "PaceCustom" {
+                    if let (Some(min), Some(max)) = (target.min_value, target.max_value) {
+                        if min > max {
+                            problems.push(ValidationProblem::PaceRange {
+                                step,
+                                name: name(),
+                                min,
+                                max,
+                            });
+                        }
+                    }
+                    continue;
+                }
+                if let (Some(min), Some(max)) = (target.min_value, target.max_value) {
+                    if min > max {
+                        problems.push(ValidationProblem::PowerRange {
+                            step,
+                            name: name(),
+                            min,
+                            max,
+                        });
+                    }
+                }
+                let watts = target
+                    .min_value
+                    .max(target.max_value)
+                    .unwrap_or(target.value);
+                if watts > MAX_WATTS {
+                    problems.push(ValidationProblem::Watts {
+                        step,
+                        name: name(),
+                        watts,
+                    });
+                }
+            }
+        }
+    }
+}
+
 /// ```json
 /// [
 ///   {
@@ -66,6 +297,7 @@ impl Default for WorkoutData {
 /// ]
 /// ```
 #[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
 pub struct WorkoutDataStructure {
     /// "type": "Step"
     /// "type": "Repetition"
@@ -105,9 +337,11 @@ pub struct WorkoutDataStructure {
 /// }
 /// ```
 #[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
 pub struct WorkoutDataStructureLength {
     /// "unit": "Power"
     /// "unit": "PowerCustom"
+    /// "unit": "PaceCustom", seconds per km (per 100 m for swimming)
     /// "unit": "Repetition"
     pub unit: String,
 
@@ -126,22 +360,296 @@ pub struct WorkoutDataStructureLength {
     pub min_value: Option<u32>,
 }
 
-const API_EDIT_CUSTOM_WORKOUT_URL: &str =
+/// `code` of a successful answer, some endpoints send the HTTP status instead
+const CODE_SUCCESS: i64 = 0;
+const CODE_OK: i64 = 200;
+
+/// The iGPS API wraps every answer in an envelope and reports business errors through it,
+/// even when the HTTP status is 200.
+///
+/// ```json
+/// {
+///     "code": 0,
+///     "message": "success",
+///     "data": 123456
+/// }
+/// ```
+#[derive(Deserialize, Debug)]
+pub struct IGPSResponse<T> {
+    /// "code": 0
+    #[serde(default)]
+    pub code: Option<i64>,
+
+    /// "success": true
+    #[serde(default)]
+    pub success: Option<bool>,
+
+    /// "message": "success"
+    #[serde(default, alias = "msg", alias = "Message")]
+    pub message: Option<String>,
+
+    pub data: Option<T>,
+}
+
+impl<T> IGPSResponse<T> {
+    /// An envelope without `success` and `code` isn't a success, e.g. `{}`
+    pub fn is_success(&self) -> bool {
+        match (self.success, self.code) {
+            (Some(success), _) => success,
+            (None, Some(code)) => code == CODE_SUCCESS || code == CODE_OK,
+            (None, None) => false,
+        }
+    }
+}
+
+/// The `data` of a successful `EditCustomWorkOut` call, either the bare id or the saved workout.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum EditCustomWorkoutData {
+    Id(u64),
+    Workout {
+        #[serde(alias = "workoutId", alias = "Id")]
+        id: u64,
+    },
+    Other(serde_json::Value),
+}
+
+impl EditCustomWorkoutData {
+    pub fn workout_id(&self) -> Option<u64> {
+        match self {
+            EditCustomWorkoutData::Id(id) | EditCustomWorkoutData::Workout { id } => Some(*id),
+            EditCustomWorkoutData::Other(value) => value.as_str().and_then(|s| s.parse().ok()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum IGPSError {
+    /// The token is missing, invalid or expired, log in again
+    TokenExpired(String),
+    /// A business error reported by the API, iGPS doesn't document its codes
+    Api { code: i64, message: String },
+    /// The body isn't an iGPS response at all
+    InvalidResponse { status: StatusCode, body: String },
+    /// The token can't be sent in a header, e.g. it contains a line break
+    InvalidToken,
+    /// The request failed before iGPS answered
+    Http(reqwest::Error),
+}
+
+impl fmt::Display for IGPSError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IGPSError::TokenExpired(msg) => write!(f, "token expired or invalid: {msg}"),
+            IGPSError::Api { code, message } => {
+                write!(f, "api error, code: {code}, msg: {message}")
+            }
+            IGPSError::InvalidResponse { status, body } => {
+                write!(f, "invalid response, status: {status}, body: {body}")
+            }
+            IGPSError::InvalidToken => write!(f, "the token isn't a valid header value"),
+            IGPSError::Http(e) => write!(f, "request fail: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for IGPSError {}
+
+/// Interpret the answer of `EditCustomWorkOut`, returns the id of the created workout if iGPS sent one.
+pub fn parse_edit_workout_response(
+    status: StatusCode,
+    body: &str,
+) -> Result<Option<u64>, IGPSError> {
+    if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+        return Err(IGPSError::TokenExpired(format!(
+            "response status: {status}"
+        )));
+    }
+    let response = match serde_json::from_str::<IGPSResponse<EditCustomWorkoutData>>(body) {
+        Ok(r) if r.success.is_some() || r.code.is_some() => r,
+        // not an iGPS envelope, e.g. `{}` or the HTML page of a proxy
+        _ => {
+            return Err(IGPSError::InvalidResponse {
+                status,
+                body: body.to_string(),
+            });
+        }
+    };
+    if status.is_success() && response.is_success() {
+        return Ok(response.data.and_then(|d| d.workout_id()));
+    }
+    let message = response.message.unwrap_or_else(|| body.to_string());
+    // `success: false` without a code carries the HTTP status
+    let code = response.code.unwrap_or_else(|| i64::from(status.as_u16()));
+    Err(IGPSError::Api { code, message })
+}
+
+/// Creates a custom workout, the body is an [`IGPSRequestBody`]
+pub const API_EDIT_CUSTOM_WORKOUT_URL: &str =
     "https://prod.zh.igpsport.com/service/mobile/api/WorkOut/EditCustomWorkOut";
 
-pub async fn push_to_igps(workout_json: String, token: String) -> Response {
-    let client = reqwest::Client::builder().build().unwrap();
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Send a serialized [`IGPSRequestBody`] to `api_url`, the answer is read by
+/// [`parse_edit_workout_response`].
+pub async fn push_to_igps(
+    api_url: &str,
+    workout_json: String,
+    token: String,
+) -> Result<Response, IGPSError> {
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .map_err(IGPSError::Http)?;
     let mut headers = HeaderMap::new();
-    headers.insert(AUTHORIZATION, token.parse().unwrap());
+    let token = HeaderValue::from_str(&token).map_err(|_| IGPSError::InvalidToken)?;
+    headers.insert(AUTHORIZATION, token);
     headers.insert(
         CONTENT_TYPE,
-        "application/json; charset=utf-8".parse().unwrap(),
+        HeaderValue::from_static("application/json; charset=utf-8"),
     );
     client
-        .post(API_EDIT_CUSTOM_WORKOUT_URL)
+        .post(api_url)
         .headers(headers)
         .body(workout_json)
         .send()
         .await
-        .unwrap()
+        .map_err(IGPSError::Http)
+}
+
+/// Download a FIT file or zip package, e.g. a training plan exported from intervals.icu
+pub async fn download(url: &str) -> Result<Vec<u8>, reqwest::Error> {
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()?;
+    let res = client.get(url).send().await?.error_for_status()?;
+    Ok(res.bytes().await?.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{repeat, step};
+
+    fn workout(title: &str, structure: Vec<WorkoutDataStructure>) -> WorkoutData {
+        WorkoutData {
+            title: title.to_string(),
+            structure,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn validate_accepts_a_workout() {
+        let workout = workout("Sweet spot", vec![step(600, 120, 150)]);
+        assert_eq!(workout.validate(), Ok(()));
+        assert!(workout.warnings().is_empty());
+    }
+
+    #[test]
+    fn validate_reports_the_title_and_missing_steps() {
+        let error = workout(" ", vec![]).validate().unwrap_err();
+        assert_eq!(
+            error.problems,
+            vec![ValidationProblem::EmptyTitle, ValidationProblem::NoSteps]
+        );
+
+        let error = workout("a\nb", vec![step(60, 1, 2)])
+            .validate()
+            .unwrap_err();
+        assert_eq!(
+            error.problems,
+            vec![ValidationProblem::TitleControlCharacters(
+                "a\nb".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn a_long_title_is_only_a_warning() {
+        let workout = workout(&"x".repeat(MAX_TITLE_LEN + 1), vec![step(60, 1, 2)]);
+        assert_eq!(workout.validate(), Ok(()));
+        assert_eq!(
+            workout.warnings(),
+            vec!["the title has 51 characters, iGPS may cut it off after 50"]
+        );
+    }
+
+    fn named(name: &str, item: WorkoutDataStructure) -> WorkoutDataStructure {
+        WorkoutDataStructure {
+            name: name.to_string(),
+            ..item
+        }
+    }
+
+    #[test]
+    fn validate_reports_every_bad_step() {
+        let on_off = vec![
+            named("on", step(60, 300, 250)),
+            named("off", step(60, 100, 2500)),
+        ];
+        let structure = vec![
+            named("warm up", step(0, 100, 150)),
+            named("2x", repeat(0, on_off)),
+        ];
+        let error = workout("Intervals", structure).validate().unwrap_err();
+        assert_eq!(
+            error.problems[2],
+            ValidationProblem::PowerRange {
+                step: 3,
+                name: "on".to_string(),
+                min: 300,
+                max: 250
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "step 1 (warm up): the duration is 0; \
+             step 2 (2x): repeats 0 times; \
+             step 3 (on): min power 300W > max power 250W; \
+             step 4 (off): 2500W is more than 2000W"
+        );
+    }
+
+    #[test]
+    fn a_success_carries_the_workout_id() {
+        let result = parse_edit_workout_response(StatusCode::OK, r#"{"code":0,"data":42}"#);
+        assert_eq!(result.unwrap(), Some(42));
+        let result = parse_edit_workout_response(StatusCode::OK, r#"{"success":true}"#);
+        assert_eq!(result.unwrap(), None);
+    }
+
+    #[test]
+    fn an_empty_answer_is_no_success() {
+        let result = parse_edit_workout_response(StatusCode::OK, "{}");
+        assert!(matches!(result, Err(IGPSError::InvalidResponse { .. })));
+        let result = parse_edit_workout_response(StatusCode::BAD_GATEWAY, "<html></html>");
+        assert!(matches!(result, Err(IGPSError::InvalidResponse { .. })));
+    }
+
+    #[test]
+    fn an_error_reports_the_code_and_message() {
+        let result = parse_edit_workout_response(
+            StatusCode::OK,
+            r#"{"code":400,"message":"title does not exist"}"#,
+        );
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "api error, code: 400, msg: title does not exist"
+        );
+        // without a code the HTTP status is reported
+        let result = parse_edit_workout_response(
+            StatusCode::CONFLICT,
+            r#"{"success":false,"message":"exists"}"#,
+        );
+        assert!(matches!(result, Err(IGPSError::Api { code: 409, .. })));
+    }
+
+    #[test]
+    fn an_unauthorized_status_is_an_expired_token() {
+        let result = parse_edit_workout_response(StatusCode::UNAUTHORIZED, "");
+        assert!(matches!(result, Err(IGPSError::TokenExpired(_))));
+        let result = parse_edit_workout_response(StatusCode::FORBIDDEN, r#"{"code":0}"#);
+        assert!(matches!(result, Err(IGPSError::TokenExpired(_))));
+    }
 }
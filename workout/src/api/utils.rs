@@ -1,17 +1,206 @@
 use crate::api::WorkoutDataStructure;
-use crate::{api, FitWorkoutArgs};
+use crate::{api, stats, Adjustment};
 use fit_rust::protocol::data_field::DataField;
 use fit_rust::protocol::message_type::MessageType;
 use fit_rust::protocol::value::Value;
 use fit_rust::protocol::FitMessage;
 use fit_rust::Fit;
+use serde::Deserialize;
+use std::fmt;
 use uuid::Uuid;
 
-pub fn build_workout_json(fit_file: Vec<u8>, fit_workout_args: &Option<FitWorkoutArgs>) -> String {
-    let fit: Fit = Fit::read(fit_file).unwrap();
+/// Why a FIT file (or iGPS workout JSON) couldn't be converted
+#[derive(Debug)]
+pub enum ConvertError {
+    /// The data isn't a FIT file
+    Fit(String),
+    /// The data looks like JSON but isn't an iGPS workout
+    Json(serde_json::Error),
+    /// The repeat step `step` repeats from step `from`, which doesn't come before it
+    Repeat { step: u16, from: u32 },
+    /// iGPS has no workouts for this FIT sport
+    Sport(String),
+    /// A field holds a value of the wrong type, e.g. a title that isn't UTF-8. `step` is the
+    /// message index of the workout step it belongs to.
+    Field {
+        field: &'static str,
+        step: Option<u16>,
+    },
+    /// The step `step` ends on something iGPS has no duration for, e.g. a heart rate
+    Duration { step: u16, duration: String },
+    /// The power target of step `step` is a percentage of an FTP nobody gave
+    Ftp { step: u16 },
+    /// The step `step` targets something iGPS has no target for, e.g. a heart rate
+    Target { step: u16, target: String },
+    /// The steps of the repetition `name` are ridden `times` times, nested repetitions
+    /// multiplied, more than [`MAX_REPETITIONS`]
+    Repetitions { name: String, times: u32 },
+}
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConvertError::Fit(msg) => write!(f, "read fit fail: {msg}"),
+            ConvertError::Json(e) => write!(f, "parse workout json fail: {e}"),
+            ConvertError::Repeat { step, from } => {
+                write!(
+                    f,
+                    "step {step}: repeats from step {from}, which isn't before it"
+                )
+            }
+            ConvertError::Sport(sport) => {
+                write!(
+                    f,
+                    "{sport} workouts aren't supported, only bike, run and swim"
+                )
+            }
+            ConvertError::Field {
+                field,
+                step: Some(step),
+            } => write!(f, "step {step}: the {field} field can't be read"),
+            ConvertError::Field { field, step: None } => {
+                write!(f, "the {field} field can't be read")
+            }
+            ConvertError::Duration { step, duration } => write!(
+                f,
+                "step {step}: {duration:?} durations aren't supported, only time, distance and repeats"
+            ),
+            ConvertError::Repetitions { name, times } => write!(
+                f,
+                "{name:?}: its steps are repeated {times} times, at most {MAX_REPETITIONS} are supported"
+            ),
+            ConvertError::Target { step, target } => write!(
+                f,
+                "step {step}: {target:?} targets aren't supported, only custom power and speed"
+            ),
+            ConvertError::Ftp { step } => write!(
+                f,
+                "step {step}: the power target is a percentage of FTP, pass --ftp"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConvertError {}
+
+/// Step names are `{name}` (the FIT step name), `{index}` (the FIT message index) and
+/// `{notes}` (the coach's notes of the step) filled into a template like this one.
+pub const DEFAULT_STEP_NAME: &str = "{name}-{index}";
+
+/// A step is ridden at most this many times, nested repetitions multiplied.
+pub const MAX_REPETITIONS: u32 = 1000;
+
+/// How the power targets of a FIT file are turned into watts for one athlete
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PowerScale {
+    /// The FTP the FIT file was planned with
+    pub source_ftp: Option<u32>,
+    /// The FTP of the athlete the workout is built for
+    pub ftp: Option<u32>,
+}
+
+impl PowerScale {
+    /// The FTP the built workout targets
+    pub fn athlete_ftp(&self) -> Option<u32> {
+        self.ftp.or(self.source_ftp)
+    }
+
+    /// FIT custom power targets are watts + 1000, values below 1000 are % FTP of the workout
+    /// step `step`. 0 is no target at all.
+    fn watts(&self, value: u32, step: u16) -> Result<u32, ConvertError> {
+        if value >= 1000 {
+            let watts = value - 1000;
+            Ok(match (self.source_ftp, self.ftp) {
+                (Some(source_ftp), Some(ftp)) if source_ftp > 0 => {
+                    (watts as f64 * ftp as f64 / source_ftp as f64).round() as u32
+                }
+                _ => watts,
+            })
+        } else if value == 0 {
+            Ok(0)
+        } else {
+            match self.athlete_ftp() {
+                Some(ftp) => Ok((value as f64 * ftp as f64 / 100.0).round() as u32),
+                None => Err(ConvertError::Ftp { step }),
+            }
+        }
+    }
+}
+
+/// The workouts saved as iGPS JSON, either the request body or the bare `data`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum WorkoutJson {
+    Body(api::IGPSRequestBody),
+    Data(api::WorkoutData),
+}
+
+/// Build the workout of a FIT file, or read it back when it's iGPS JSON already.
+///
+/// The adjustment, the power scale and the step names only apply to FIT files.
+pub fn load_workout(
+    data: Vec<u8>,
+    adjustment: &Option<Adjustment>,
+    power_scale: PowerScale,
+    step_name: &str,
+) -> Result<api::WorkoutData, ConvertError> {
+    if !is_json(&data) {
+        return build_workout(data, adjustment, power_scale, step_name);
+    }
+    let mut workout_data = match serde_json::from_slice(&data).map_err(ConvertError::Json)? {
+        WorkoutJson::Body(body) => body.data,
+        WorkoutJson::Data(data) => data,
+    };
+    check_repetitions(&workout_data.structure, 1)?;
+    workout_data.total_time = stats::total_duration(&workout_data.structure);
+    Ok(workout_data)
+}
+
+/// Reject steps that are repeated more than [`MAX_REPETITIONS`] times, `times` is how often
+/// `structure` itself is ridden
+fn check_repetitions(structure: &[WorkoutDataStructure], times: u32) -> Result<(), ConvertError> {
+    for item in structure {
+        let Some(children) = &item.steps else {
+            continue;
+        };
+        let times = times.saturating_mul(stats::repetitions(item));
+        if times > MAX_REPETITIONS {
+            return Err(ConvertError::Repetitions {
+                name: item.name.clone(),
+                times,
+            });
+        }
+        check_repetitions(children, times)?;
+    }
+    Ok(())
+}
+
+/// FIT files start with their header size, JSON with `{`
+pub fn is_json(data: &[u8]) -> bool {
+    data.iter()
+        .find(|b| !b.is_ascii_whitespace())
+        .is_some_and(|b| *b == b'{')
+}
+
+/// Convert a FIT workout file into an iGPS workout.
+///
+/// `adjustment` adjusts the power or duration of every step, `power_scale` turns the
+/// power targets into watts for the athlete. The steps are named after the `step_name` template,
+/// see [`DEFAULT_STEP_NAME`]; step notes it doesn't use are added to the description.
+pub fn build_workout(
+    fit_file: Vec<u8>,
+    adjustment: &Option<Adjustment>,
+    power_scale: PowerScale,
+    step_name: &str,
+) -> Result<api::WorkoutData, ConvertError> {
+    let fit: Fit =
+        Fit::read(fit_file).map_err(|e| ConvertError::Fit(e.root_cause().to_string()))?;
     let mut workout_data = api::WorkoutData {
         ..Default::default()
     };
+    let mut wkt_description = String::new();
+    // the notes of the steps, when the step names don't carry them
+    let mut cues = Vec::new();
     for data in &fit.data {
         match data {
             FitMessage::Definition(_) => {}
@@ -19,60 +208,84 @@ pub fn build_workout_json(fit_file: Vec<u8>, fit_workout_args: &Option<FitWorkou
                 match msg.data.message_type {
                     MessageType::FileId | MessageType::Workout => {
                         for item in &msg.data.values {
-                            match item.field_num {
-                                8 => {
-                                    if msg.data.message_type == MessageType::FileId {
-                                        workout_data.description =
-                                            <Value as Clone>::clone(&item.value).into();
-                                    } else {
-                                        workout_data.title =
-                                            <Value as Clone>::clone(&item.value).into();
-                                    }
+                            if item.field_num == 8 {
+                                if msg.data.message_type == MessageType::FileId {
+                                    workout_data.description =
+                                        fit_text(&item.value).unwrap_or_default();
+                                } else {
+                                    workout_data.title =
+                                        fit_string(&item.value).ok_or(ConvertError::Field {
+                                            field: "wkt_name",
+                                            step: None,
+                                        })?;
                                 }
-                                _ => {}
+                            } else if item.field_num == 17
+                                && msg.data.message_type == MessageType::Workout
+                            {
+                                // wkt_description, fit_rust's profile doesn't know it
+                                wkt_description = fit_text(&item.value).unwrap_or_default();
+                            } else if item.field_num == 4
+                                && msg.data.message_type == MessageType::Workout
+                            {
+                                // sport, fit_rust leaves the number of sports it can't name
+                                let sport = fit_enum(&item.value)
+                                    .map(str::to_string)
+                                    .unwrap_or_else(|| format!("sport {:?}", item.value));
+                                workout_data.workout_type = workout_type(&sport)
+                                    .ok_or(ConvertError::Sport(sport))?
+                                    .to_string();
                             }
                         }
                     }
                     MessageType::WorkoutStep => {
-                        let workout_step = get_workout_step(&msg.data.values);
+                        let workout_step = get_workout_step(&msg.data.values)?;
                         match workout_step.duration_type.as_str() {
-                            "time" => {
-                                workout_data.total_time += workout_step.duration_value / 1000;
-                                let intensity_class = match workout_data.structure.len() {
-                                    0 => "WarmUp",
+                            duration_type @ ("time" | "distance") => {
+                                let intensity_class = match workout_step.intensity.as_str() {
+                                    "warmup" => "WarmUp",
+                                    "rest" | "recovery" => "Rest",
+                                    "cooldown" => "CoolDown",
+                                    // a file without intensities starts with the warm-up
+                                    "" if workout_data.structure.is_empty() => "WarmUp",
                                     _ => "Active",
                                 };
-                                let mut max_value: u32 = workout_step.target_value_high - 1000;
-                                let mut min_value: u32 = workout_step.target_value_low - 1000;
-                                let mut duration_value: u32 = workout_step.duration_value / 1000;
-                                match fit_workout_args {
-                                    None => {}
-                                    Some(args) => {
-                                        args.apply_operation(
-                                            &mut min_value,
-                                            &mut max_value,
-                                            &mut duration_value,
-                                        );
-                                    }
-                                }
+                                let distance = duration_type == "distance";
+                                // FIT times are ms, distances cm. A duration adjustment changes
+                                // the seconds of time steps only.
+                                let mut duration_value: u32 = if distance {
+                                    0
+                                } else {
+                                    workout_step.duration_value / 1000
+                                };
+                                let intensity_target = intensity_target(
+                                    &workout_step,
+                                    &workout_data.workout_type,
+                                    adjustment,
+                                    power_scale,
+                                    &mut duration_value,
+                                )?;
 
+                                if !workout_step.notes.is_empty() && !step_name.contains("{notes}")
+                                {
+                                    cues.push(format!(
+                                        "{}: {}",
+                                        workout_step.name(step_name),
+                                        workout_step.notes
+                                    ));
+                                }
                                 workout_data.structure.push(WorkoutDataStructure {
                                     workout_type: "Step".to_string(),
-                                    name: format!(
-                                        "{}-{}",
-                                        workout_step.step_name, workout_step.index
-                                    ),
+                                    name: workout_step.name(step_name),
                                     uuid: Uuid::new_v4().to_string(),
                                     intensity_class: intensity_class.into(),
-                                    intensity_target: Some(api::WorkoutDataStructureLength {
-                                        unit: "PowerCustom".to_string(),
-                                        value: 0,
-                                        max_value: Some(max_value),
-                                        min_value: Some(min_value),
-                                    }),
+                                    intensity_target,
                                     length: api::WorkoutDataStructureLength {
-                                        unit: "Second".to_string(),
-                                        value: duration_value,
+                                        unit: if distance { "Meter" } else { "Second" }.to_string(),
+                                        value: if distance {
+                                            workout_step.duration_value / 100
+                                        } else {
+                                            duration_value
+                                        },
                                         max_value: None,
                                         min_value: None,
                                     },
@@ -82,17 +295,19 @@ pub fn build_workout_json(fit_file: Vec<u8>, fit_workout_args: &Option<FitWorkou
                             }
                             "repeat_until_steps_cmplt" => {
                                 // index - duration_value
-                                let count = workout_step.index as usize
-                                    - workout_step.duration_value as usize;
+                                let count = (workout_step.index as usize)
+                                    .checked_sub(workout_step.duration_value as usize)
+                                    .filter(|count| *count <= workout_data.structure.len())
+                                    .ok_or(ConvertError::Repeat {
+                                        step: workout_step.index,
+                                        from: workout_step.duration_value,
+                                    })?;
                                 let repeat_data_vec = workout_data
                                     .structure
                                     .split_off(workout_data.structure.len() - count);
                                 workout_data.structure.push(WorkoutDataStructure {
                                     workout_type: "Repetition".to_string(),
-                                    name: format!(
-                                        "{}-{}",
-                                        workout_step.step_name, workout_step.index
-                                    ),
+                                    name: workout_step.name(step_name),
                                     uuid: Uuid::new_v4().to_string(),
                                     intensity_class: "Active".into(),
                                     intensity_target: None,
@@ -106,7 +321,12 @@ pub fn build_workout_json(fit_file: Vec<u8>, fit_workout_args: &Option<FitWorkou
                                     steps: Some(repeat_data_vec),
                                 });
                             }
-                            _ => {}
+                            duration => {
+                                return Err(ConvertError::Duration {
+                                    step: workout_step.index,
+                                    duration: duration.to_string(),
+                                })
+                            }
                         };
                     }
                     _ => {}
@@ -115,7 +335,160 @@ pub fn build_workout_json(fit_file: Vec<u8>, fit_workout_args: &Option<FitWorkou
         }
     }
 
-    serde_json::to_string(&api::IGPSRequestBody { data: workout_data }).unwrap()
+    if !wkt_description.is_empty() {
+        workout_data.description = wkt_description;
+    }
+    for cue in cues {
+        if !workout_data.description.is_empty() {
+            workout_data.description.push('\n');
+        }
+        workout_data.description.push_str(&cue);
+    }
+    // repetitions are only known after their steps, so count the time once the tree is built
+    check_repetitions(&workout_data.structure, 1)?;
+    workout_data.total_time = stats::total_duration(&workout_data.structure);
+    Ok(workout_data)
+}
+
+/// The iGPS workout type of a FIT sport, `None` for sports iGPS has no workouts for
+fn workout_type(sport: &str) -> Option<&'static str> {
+    match sport {
+        "" | "generic" | "cycling" | "e_biking" => Some("bike"),
+        "running" => Some("run"),
+        "swimming" => Some("swim"),
+        _ => None,
+    }
+}
+
+/// FIT speed targets are mm/s, iGPS wants the pace in seconds per km (per 100 m for swimming).
+///
+/// The faster speed is the lower pace.
+fn pace_target(
+    workout_step: &FitWorkoutStep,
+    workout_type: &str,
+) -> Option<api::WorkoutDataStructureLength> {
+    let metres = if workout_type == "swim" { 100 } else { 1000 };
+    let min_value = pace(workout_step.target_value_high, metres);
+    let max_value = pace(workout_step.target_value_low, metres);
+    if min_value.is_none() && max_value.is_none() {
+        return None;
+    }
+    Some(api::WorkoutDataStructureLength {
+        unit: "PaceCustom".to_string(),
+        value: 0,
+        min_value: min_value.or(max_value),
+        max_value: max_value.or(min_value),
+    })
+}
+
+/// Seconds `metres` take at `speed` mm/s, rounded. A speed of 0 is no bound of the target.
+fn pace(speed: u32, metres: u64) -> Option<u32> {
+    let millimetres = metres * 1000;
+    (millimetres + speed as u64 / 2)
+        .checked_div(speed as u64)
+        .map(|secs| u32::try_from(secs).unwrap_or(u32::MAX))
+}
+
+/// The iGPS target of a time or distance step, the adjustment changes its power and `duration`.
+///
+/// Open steps and steps without target values have no target. FIT targets are custom values
+/// when `target_value` is 0, otherwise a zone of the athlete, which iGPS has no target for just
+/// like heart rate and cadence.
+fn intensity_target(
+    workout_step: &FitWorkoutStep,
+    workout_type: &str,
+    adjustment: &Option<Adjustment>,
+    power_scale: PowerScale,
+    duration: &mut u32,
+) -> Result<Option<api::WorkoutDataStructureLength>, ConvertError> {
+    let custom = workout_step.target_value == 0;
+    let no_values =
+        custom && workout_step.target_value_low == 0 && workout_step.target_value_high == 0;
+    let mut power = None;
+    let pace = match workout_step.target_type.as_str() {
+        "open" | "" => None,
+        _ if no_values => None,
+        "speed" | "speed_lap" if custom => pace_target(workout_step, workout_type),
+        "power" | "power_3s" | "power_10s" | "power_30s" | "power_lap" if custom => {
+            power = Some((
+                power_scale.watts(workout_step.target_value_low, workout_step.index)?,
+                power_scale.watts(workout_step.target_value_high, workout_step.index)?,
+            ));
+            None
+        }
+        target_type => {
+            return Err(ConvertError::Target {
+                step: workout_step.index,
+                target: if custom {
+                    target_type.to_string()
+                } else {
+                    format!("{target_type} zone")
+                },
+            })
+        }
+    };
+    // a power adjustment doesn't change a pace
+    let (mut min_value, mut max_value) = power.unwrap_or_default();
+    if let Some(adjustment) = adjustment {
+        adjustment.apply_operation(&mut min_value, &mut max_value, duration);
+    }
+    Ok(match power {
+        Some(_) => Some(api::WorkoutDataStructureLength {
+            unit: "PowerCustom".to_string(),
+            value: 0,
+            max_value: Some(max_value),
+            min_value: Some(min_value),
+        }),
+        None => pace,
+    })
+}
+
+/// FIT strings keep their NUL padding, e.g. `"W2_5_125\0"`. `None` when the value isn't a
+/// string, fit_rust reads strings that aren't UTF-8 as `Value::None`.
+fn fit_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.trim_end_matches('\0').to_string()),
+        Value::Enum(s) => Some(s.to_string()),
+        _ => None,
+    }
+}
+
+/// Free text like notes and descriptions, coaches write them in any encoding. Byte arrays are
+/// decoded lossily, `None` for anything else, including strings that weren't UTF-8.
+fn fit_text(value: &Value) -> Option<String> {
+    match value {
+        Value::ArrU8(bytes) => Some(
+            String::from_utf8_lossy(bytes)
+                .trim_end_matches('\0')
+                .to_string(),
+        ),
+        _ => fit_string(value),
+    }
+}
+
+/// The name of an enum value, `""` when the field is invalid (not set) and `None` for values
+/// fit_rust can't name
+fn fit_enum(value: &Value) -> Option<&'static str> {
+    match value {
+        Value::Enum(s) => Some(s),
+        Value::U8(u8::MAX) | Value::U16(u16::MAX) | Value::None => Some(""),
+        _ => None,
+    }
+}
+
+/// An unsigned integer of any width, `None` for strings, arrays and negative or too large values
+fn fit_number(value: &Value) -> Option<u32> {
+    match value {
+        Value::U8(v) => Some(u32::from(*v)),
+        Value::U16(v) => Some(u32::from(*v)),
+        Value::U32(v) | Value::Time(v) => Some(*v),
+        Value::U64(v) => u32::try_from(*v).ok(),
+        Value::I8(v) => u32::try_from(*v).ok(),
+        Value::I16(v) => u32::try_from(*v).ok(),
+        Value::I32(v) => u32::try_from(*v).ok(),
+        Value::I64(v) => u32::try_from(*v).ok(),
+        _ => None,
+    }
 }
 
 #[derive(Debug, Default)]
@@ -128,53 +501,312 @@ struct FitWorkoutStep {
     target_value_low: u32,
     target_value_high: u32,
     intensity: String,
+    notes: String,
     index: u16,
 }
 
-fn get_workout_step(data_field_vec: &Vec<DataField>) -> FitWorkoutStep {
+impl FitWorkoutStep {
+    /// Fill `{name}`, `{index}` and `{notes}` of the step name template
+    fn name(&self, template: &str) -> String {
+        template
+            .replace("{name}", &self.step_name)
+            .replace("{index}", &self.index.to_string())
+            .replace("{notes}", &self.notes)
+            .trim()
+            .to_string()
+    }
+}
+
+fn get_workout_step(data_field_vec: &[DataField]) -> Result<FitWorkoutStep, ConvertError> {
     let mut workout_step_fit = FitWorkoutStep {
         ..Default::default()
     };
+    // the message index names the step in errors, whichever field comes first
+    if let Some(item) = data_field_vec.iter().find(|item| item.field_num == 254) {
+        workout_step_fit.index = fit_number(&item.value)
+            .and_then(|index| u16::try_from(index).ok())
+            .ok_or(ConvertError::Field {
+                field: "message_index",
+                step: None,
+            })?;
+    }
+    let invalid = |field| ConvertError::Field {
+        field,
+        step: Some(workout_step_fit.index),
+    };
     for item in data_field_vec {
+        let value = &item.value;
         match item.field_num {
             0 => {
-                // wkt_step_name
-                workout_step_fit.step_name = <Value as Clone>::clone(&item.value).into();
+                workout_step_fit.step_name =
+                    fit_string(value).ok_or_else(|| invalid("wkt_step_name"))?;
             }
             1 => {
-                // duration_type
-                workout_step_fit.duration_type = <Value as Clone>::clone(&item.value).into();
+                workout_step_fit.duration_type = fit_enum(value)
+                    .ok_or_else(|| invalid("duration_type"))?
+                    .to_string();
             }
             2 => {
-                // duration_value
-                workout_step_fit.duration_value = <Value as Clone>::clone(&item.value).into();
+                workout_step_fit.duration_value =
+                    fit_number(value).ok_or_else(|| invalid("duration_value"))?;
             }
             3 => {
-                // target_type
-                workout_step_fit.target_type = <Value as Clone>::clone(&item.value).into();
+                workout_step_fit.target_type = fit_enum(value)
+                    .ok_or_else(|| invalid("target_type"))?
+                    .to_string();
             }
             4 => {
-                // target_value
-                workout_step_fit.target_value = <Value as Clone>::clone(&item.value).into();
+                workout_step_fit.target_value =
+                    fit_number(value).ok_or_else(|| invalid("target_value"))?;
             }
             5 => {
-                // custom_target_value_low
-                workout_step_fit.target_value_low = <Value as Clone>::clone(&item.value).into();
+                workout_step_fit.target_value_low =
+                    fit_number(value).ok_or_else(|| invalid("custom_target_value_low"))?;
             }
             6 => {
-                // custom_target_value_high
-                workout_step_fit.target_value_high = <Value as Clone>::clone(&item.value).into();
+                workout_step_fit.target_value_high =
+                    fit_number(value).ok_or_else(|| invalid("custom_target_value_high"))?;
             }
             7 => {
-                // intensity
-                workout_step_fit.intensity = <Value as Clone>::clone(&item.value).into();
+                workout_step_fit.intensity = fit_enum(value)
+                    .ok_or_else(|| invalid("intensity"))?
+                    .to_string();
             }
-            254 => {
-                // message_index
-                workout_step_fit.index = <Value as Clone>::clone(&item.value).into();
+            8 => {
+                workout_step_fit.notes = fit_text(value).unwrap_or_default();
             }
             _ => {}
         }
     }
-    workout_step_fit
+    Ok(workout_step_fit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{fit_workout, repeat, step, FitStep};
+
+    const W2_5_125: &[u8] = include_bytes!("../../examples/W2_5_125.fit");
+
+    #[test]
+    fn total_time_counts_every_repetition() {
+        let workout_data = build_workout(
+            W2_5_125.to_vec(),
+            &None,
+            PowerScale::default(),
+            DEFAULT_STEP_NAME,
+        )
+        .unwrap();
+        let repeat = &workout_data.structure[1];
+        assert_eq!(workout_data.structure.len(), 3);
+        assert_eq!(repeat.workout_type, "Repetition");
+        assert_eq!(repeat.length.value, 10);
+        assert_eq!(repeat.steps.as_ref().map(Vec::len), Some(2));
+        // 4 min warm up, 10 x (4 + 2) min, 4 min cool down
+        assert_eq!(workout_data.total_time, 4 * 60 + 10 * 6 * 60 + 4 * 60);
+    }
+
+    fn threshold(step_name: &str) -> api::WorkoutData {
+        let steps = [
+            FitStep {
+                name: "Warm up",
+                intensity: 2,
+                ..Default::default()
+            },
+            FitStep {
+                name: "Tempo",
+                notes: "stay seated, 85 rpm",
+                ..Default::default()
+            },
+            FitStep {
+                name: "Easy",
+                intensity: 1,
+                notes: "spin",
+                ..Default::default()
+            },
+        ];
+        let fit = fit_workout("Threshold", "Sweet spot", &steps);
+        build_workout(fit, &None, PowerScale::default(), step_name).unwrap()
+    }
+
+    fn step_names(workout_data: &api::WorkoutData) -> Vec<&str> {
+        workout_data
+            .structure
+            .iter()
+            .map(|item| item.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn step_names_fill_in_the_template() {
+        let workout_data = threshold(DEFAULT_STEP_NAME);
+        assert_eq!(
+            step_names(&workout_data),
+            vec!["Warm up-0", "Tempo-1", "Easy-2"]
+        );
+        let workout_data = threshold("{index}. {name} {notes}");
+        assert_eq!(
+            step_names(&workout_data),
+            vec!["0. Warm up", "1. Tempo stay seated, 85 rpm", "2. Easy spin"]
+        );
+    }
+
+    #[test]
+    fn notes_the_template_leaves_out_go_into_the_description() {
+        let workout_data = threshold(DEFAULT_STEP_NAME);
+        assert_eq!(
+            workout_data.description,
+            "Sweet spot\nTempo-1: stay seated, 85 rpm\nEasy-2: spin"
+        );
+        let workout_data = threshold("{name}: {notes}");
+        assert_eq!(workout_data.description, "Sweet spot");
+        assert_eq!(workout_data.title, "Threshold");
+    }
+
+    #[test]
+    fn a_broken_file_is_an_error() {
+        let result = build_workout(
+            W2_5_125[..40].to_vec(),
+            &None,
+            PowerScale::default(),
+            DEFAULT_STEP_NAME,
+        );
+        assert!(matches!(result, Err(ConvertError::Fit(_))));
+    }
+
+    fn target_step(target_type: &str, target_value: u32, low: u32, high: u32) -> FitWorkoutStep {
+        FitWorkoutStep {
+            target_type: target_type.to_string(),
+            target_value,
+            target_value_low: low,
+            target_value_high: high,
+            index: 4,
+            ..Default::default()
+        }
+    }
+
+    fn target(
+        workout_step: &FitWorkoutStep,
+    ) -> Result<Option<api::WorkoutDataStructureLength>, ConvertError> {
+        intensity_target(workout_step, "bike", &None, PowerScale::default(), &mut 60)
+    }
+
+    #[test]
+    fn open_steps_have_no_target() {
+        assert!(target(&target_step("open", 0, 0, 0)).unwrap().is_none());
+        assert!(target(&target_step("power", 0, 0, 0)).unwrap().is_none());
+        assert!(target(&target_step("heart_rate", 0, 0, 0))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn custom_power_and_speed_are_targets() {
+        let power = target(&target_step("power", 0, 1200, 1250))
+            .unwrap()
+            .unwrap();
+        assert_eq!(power.unit, "PowerCustom");
+        assert_eq!((power.min_value, power.max_value), (Some(200), Some(250)));
+        let pace = target(&target_step("speed", 0, 2500, 3000))
+            .unwrap()
+            .unwrap();
+        assert_eq!(pace.unit, "PaceCustom");
+    }
+
+    #[test]
+    fn pace_per_km_and_per_100m_for_swimming() {
+        // 4 m/s is 4:10 per km, 1.25 m/s 1:20 per 100 m
+        assert_eq!(pace(4000, 1000), Some(250));
+        assert_eq!(pace(1250, 100), Some(80));
+        assert_eq!(pace(3, 1000), Some(333_333));
+        assert_eq!(pace(0, 1000), None);
+
+        let run = target_step("speed", 0, 2500, 4000);
+        let target = pace_target(&run, "run").unwrap();
+        // the faster speed is the lower pace
+        assert_eq!((target.min_value, target.max_value), (Some(250), Some(400)));
+        let target = pace_target(&run, "swim").unwrap();
+        assert_eq!((target.min_value, target.max_value), (Some(25), Some(40)));
+    }
+
+    #[test]
+    fn a_speed_of_zero_is_no_bound() {
+        let target = pace_target(&target_step("speed", 0, 0, 4000), "run").unwrap();
+        assert_eq!((target.min_value, target.max_value), (Some(250), Some(250)));
+        assert!(pace_target(&target_step("speed", 0, 0, 0), "run").is_none());
+    }
+
+    #[test]
+    fn other_targets_are_an_error() {
+        for (workout_step, expected) in [
+            (target_step("heart_rate", 0, 130, 150), "heart_rate"),
+            (target_step("heart_rate", 3, 0, 0), "heart_rate zone"),
+            (target_step("cadence", 0, 85, 95), "cadence"),
+            (target_step("power", 4, 0, 0), "power zone"),
+            (target_step("speed", 2, 0, 0), "speed zone"),
+        ] {
+            match target(&workout_step) {
+                Err(ConvertError::Target { step: 4, target }) => assert_eq!(target, expected),
+                result => panic!("{expected}: {result:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn watts_are_scaled_to_the_athlete() {
+        let scale = PowerScale {
+            source_ftp: Some(250),
+            ftp: Some(200),
+        };
+        assert_eq!(scale.watts(1250, 1).unwrap(), 200);
+        assert_eq!(scale.watts(75, 1).unwrap(), 150);
+        assert_eq!(PowerScale::default().watts(1250, 1).unwrap(), 250);
+        assert_eq!(PowerScale::default().watts(0, 1).unwrap(), 0);
+    }
+
+    #[test]
+    fn a_percentage_of_ftp_needs_an_ftp() {
+        let result = PowerScale::default().watts(75, 3);
+        assert!(matches!(result, Err(ConvertError::Ftp { step: 3 })));
+        let scale = PowerScale {
+            source_ftp: Some(250),
+            ftp: None,
+        };
+        assert_eq!(scale.watts(75, 3).unwrap(), 188);
+    }
+
+    #[test]
+    fn repetitions_are_limited() {
+        let structure = vec![repeat(MAX_REPETITIONS, vec![step(10, 100, 200)])];
+        assert!(check_repetitions(&structure, 1).is_ok());
+        let structure = vec![repeat(MAX_REPETITIONS + 1, vec![step(10, 100, 200)])];
+        assert!(matches!(
+            check_repetitions(&structure, 1),
+            Err(ConvertError::Repetitions { times: 1001, .. })
+        ));
+        // nested repetitions multiply
+        let structure = vec![repeat(40, vec![repeat(30, vec![step(10, 100, 200)])])];
+        assert!(matches!(
+            check_repetitions(&structure, 1),
+            Err(ConvertError::Repetitions { times: 1200, .. })
+        ));
+    }
+
+    #[test]
+    fn a_json_workout_repeated_too_often_is_an_error() {
+        let body = api::IGPSRequestBody {
+            data: api::WorkoutData {
+                title: "Intervals".to_string(),
+                structure: vec![repeat(u32::MAX, vec![step(10, 100, 200)])],
+                ..Default::default()
+            },
+        };
+        let result = load_workout(
+            serde_json::to_vec(&body).unwrap(),
+            &None,
+            PowerScale::default(),
+            DEFAULT_STEP_NAME,
+        );
+        assert!(matches!(result, Err(ConvertError::Repetitions { .. })));
+    }
 }
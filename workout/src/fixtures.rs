@@ -0,0 +1,126 @@
+//! Workouts built in code for the tests
+use crate::api::{WorkoutDataStructure, WorkoutDataStructureLength};
+
+/// A step of `secs` seconds with a power target of `min` to `max` watts
+pub(crate) fn step(secs: u32, min: u32, max: u32) -> WorkoutDataStructure {
+    WorkoutDataStructure {
+        workout_type: "Step".to_string(),
+        length: WorkoutDataStructureLength {
+            unit: "Second".to_string(),
+            value: secs,
+            ..Default::default()
+        },
+        intensity_target: Some(WorkoutDataStructureLength {
+            unit: "PowerCustom".to_string(),
+            min_value: Some(min),
+            max_value: Some(max),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// `steps` repeated `count` times
+pub(crate) fn repeat(count: u32, steps: Vec<WorkoutDataStructure>) -> WorkoutDataStructure {
+    WorkoutDataStructure {
+        workout_type: "Repetition".to_string(),
+        length: WorkoutDataStructureLength {
+            unit: "Repetition".to_string(),
+            value: count,
+            ..Default::default()
+        },
+        steps: Some(steps),
+        ..Default::default()
+    }
+}
+
+/// One `workout_step` of [`fit_workout`], the enums are the numbers of the FIT profile
+#[derive(Clone, Copy)]
+pub(crate) struct FitStep<'a> {
+    pub(crate) name: &'a str,
+    /// 0 time (ms), 1 distance (cm), 6 repeat until steps complete
+    pub(crate) duration_type: u8,
+    pub(crate) duration_value: u32,
+    /// 0 speed, 1 heart rate, 2 open, 3 cadence, 4 power
+    pub(crate) target_type: u8,
+    pub(crate) target_value: u32,
+    pub(crate) low: u32,
+    pub(crate) high: u32,
+    /// 0 active, 1 rest, 2 warm-up, 3 cool-down
+    pub(crate) intensity: u8,
+    pub(crate) notes: &'a str,
+}
+
+impl Default for FitStep<'_> {
+    /// 5 minutes at 200-250 W
+    fn default() -> Self {
+        FitStep {
+            name: "",
+            duration_type: 0,
+            duration_value: 300_000,
+            target_type: 4,
+            target_value: 0,
+            low: 1200,
+            high: 1250,
+            intensity: 0,
+            notes: "",
+        }
+    }
+}
+
+/// A FIT bike workout file
+pub(crate) fn fit_workout(title: &str, description: &str, steps: &[FitStep]) -> Vec<u8> {
+    let mut body = Vec::new();
+    // file_id: type = workout
+    body.extend([0x40, 0, 0, 0, 0, 1, 0, 1, 0, 0x00, 5]);
+    // workout: sport, wkt_name, wkt_description
+    body.extend([0x41, 0, 0, 26, 0, 3, 4, 1, 0, 8, 16, 7, 17, 32, 7]);
+    body.extend([0x01, 2]);
+    body.extend(fit_string(title, 16));
+    body.extend(fit_string(description, 32));
+    // workout_step: wkt_step_name, duration_type, duration_value, target_type, target_value,
+    // custom_target_value_low, custom_target_value_high, intensity, notes, message_index
+    body.extend([
+        0x42, 0, 0, 27, 0, 10, 0, 16, 7, 1, 1, 0, 2, 4, 0x86, 3, 1, 0, 4, 4, 0x86,
+    ]);
+    body.extend([5, 4, 0x86, 6, 4, 0x86, 7, 1, 0, 8, 32, 7, 254, 2, 0x84]);
+    for (index, step) in steps.iter().enumerate() {
+        body.push(0x02);
+        body.extend(fit_string(step.name, 16));
+        body.push(step.duration_type);
+        body.extend(step.duration_value.to_le_bytes());
+        body.push(step.target_type);
+        for value in [step.target_value, step.low, step.high] {
+            body.extend(value.to_le_bytes());
+        }
+        body.push(step.intensity);
+        body.extend(fit_string(step.notes, 32));
+        body.extend((index as u16).to_le_bytes());
+    }
+    let mut data = vec![14, 0x10];
+    data.extend(2132_u16.to_le_bytes());
+    data.extend((body.len() as u32).to_le_bytes());
+    data.extend(b".FIT");
+    data.extend(fit_crc(&data).to_le_bytes());
+    data.extend(body);
+    data.extend(fit_crc(&data).to_le_bytes());
+    data
+}
+
+/// A NUL padded string of `size` bytes
+fn fit_string(s: &str, size: usize) -> Vec<u8> {
+    let mut bytes = s.as_bytes()[..s.len().min(size - 1)].to_vec();
+    bytes.resize(size, 0);
+    bytes
+}
+
+fn fit_crc(data: &[u8]) -> u16 {
+    const TABLE: [u16; 16] = [
+        0x0000, 0xCC01, 0xD801, 0x1400, 0xF001, 0x3C00, 0x2800, 0xE401, 0xA001, 0x6C00, 0x7800,
+        0xB401, 0x5000, 0x9C01, 0x8801, 0x4400,
+    ];
+    data.iter().fold(0, |crc, byte| {
+        let crc = (crc >> 4) ^ TABLE[(crc & 0xF) as usize] ^ TABLE[(byte & 0xF) as usize];
+        (crc >> 4) ^ TABLE[(crc & 0xF) as usize] ^ TABLE[(byte >> 4) as usize]
+    })
+}
@@ -0,0 +1,207 @@
+use std::fmt::Write;
+use workout::api::WorkoutData;
+use workout::stats;
+
+const WIDTH: u32 = 800;
+const HEIGHT: u32 = 320;
+/// Space left for the title above and the time axis below the chart
+const HEADER: u32 = 64;
+const FOOTER: u32 = 40;
+/// Space left for the watt labels on the left
+const MARGIN: u32 = 48;
+
+/// Fill of each intensity class, unknown classes are drawn like `Active`
+const CLASS_COLORS: [(&str, &str); 4] = [
+    ("WarmUp", "#5b9bd5"),
+    ("Active", "#f28e2b"),
+    ("Rest", "#8cc084"),
+    ("CoolDown", "#76b7b2"),
+];
+
+/// Image formats `workout export` writes
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum, Debug)]
+pub(crate) enum ImageFormat {
+    Svg,
+    /// Needs the `png` feature
+    Png,
+}
+
+impl ImageFormat {
+    pub(crate) fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Svg => "svg",
+            ImageFormat::Png => "png",
+        }
+    }
+}
+
+/// Render `workout_data` in `format`
+pub(crate) fn render(
+    workout_data: &WorkoutData,
+    ftp: Option<u32>,
+    format: ImageFormat,
+) -> Result<Vec<u8>, String> {
+    let svg = render_svg(workout_data, ftp);
+    match format {
+        ImageFormat::Svg => Ok(svg.into_bytes()),
+        ImageFormat::Png => svg_to_png(&svg),
+    }
+}
+
+/// The power profile as SVG: title, duration and TSS on top, one block per step colored by
+/// intensity class, and the FTP line when it's known.
+pub(crate) fn render_svg(workout_data: &WorkoutData, ftp: Option<u32>) -> String {
+    let ftp = ftp.filter(|f| *f > 0);
+    let stats = stats::calculate(&workout_data.structure, ftp);
+    let steps = stats::expand_steps(&workout_data.structure);
+    let duration = stats.duration.max(1);
+    let top = steps
+        .iter()
+        .map(|item| stats::target_watts(item))
+        .chain(ftp)
+        .max()
+        .unwrap_or(0)
+        .max(1) as f64
+        * 1.1;
+
+    let chart_width = (WIDTH - MARGIN * 2) as f64;
+    let chart_height = (HEIGHT - HEADER - FOOTER) as f64;
+    let bottom = (HEIGHT - FOOTER) as f64;
+    let x = |secs: u32| MARGIN as f64 + secs as f64 * chart_width / duration as f64;
+    let y = |watts: u32| bottom - watts as f64 * chart_height / top;
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{WIDTH}" height="{HEIGHT}" viewBox="0 0 {WIDTH} {HEIGHT}" font-family="Helvetica, Arial, DejaVu Sans, sans-serif">"#
+    );
+    let _ = writeln!(
+        svg,
+        r##"<rect width="{WIDTH}" height="{HEIGHT}" fill="#ffffff"/>"##
+    );
+    let _ = writeln!(
+        svg,
+        r##"<text x="{MARGIN}" y="28" font-size="20" font-weight="bold" fill="#222222">{}</text>"##,
+        escape(&workout_data.title)
+    );
+    let mut subtitle = format!("Duration {}", stats::format_duration(stats.duration));
+    if let Some(tss) = stats.tss {
+        let _ = write!(subtitle, " · TSS {:.0}", tss);
+    }
+    if let Some(intensity_factor) = stats.intensity_factor {
+        let _ = write!(subtitle, " · IF {:.2}", intensity_factor);
+    }
+    let _ = writeln!(
+        svg,
+        r##"<text x="{MARGIN}" y="50" font-size="14" fill="#555555">{}</text>"##,
+        escape(&subtitle)
+    );
+
+    if steps.iter().all(|item| stats::target_watts(item) == 0) {
+        // e.g. a run with pace targets, the blocks would all be flat
+        let _ = writeln!(
+            svg,
+            r##"<text x="{}" y="{:.1}" font-size="14" text-anchor="middle" fill="#555555">no power targets</text>"##,
+            WIDTH / 2,
+            bottom - chart_height / 2.0
+        );
+    }
+    let mut start = 0;
+    for item in &steps {
+        let watts = stats::target_watts(item);
+        let _ = writeln!(
+            svg,
+            r##"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="{}" stroke="#ffffff"><title>{} {} {}W</title></rect>"##,
+            x(start),
+            y(watts),
+            x(start + stats::step_seconds(item)) - x(start),
+            bottom - y(watts),
+            class_color(&item.intensity_class),
+            escape(&item.name),
+            stats::format_length(item),
+            watts
+        );
+        start += stats::step_seconds(item);
+    }
+
+    if let Some(ftp) = ftp {
+        let _ = writeln!(
+            svg,
+            r##"<line x1="{MARGIN}" y1="{0:.1}" x2="{1}" y2="{0:.1}" stroke="#d62728" stroke-dasharray="6 4"/>"##,
+            y(ftp),
+            WIDTH - MARGIN
+        );
+        let _ = writeln!(
+            svg,
+            r##"<text x="{}" y="{:.1}" font-size="12" text-anchor="end" fill="#d62728">FTP {ftp}W</text>"##,
+            WIDTH - MARGIN,
+            y(ftp) - 4.0
+        );
+    }
+
+    // axes, the watts of the top and a time tick every 10 minutes (5 for short workouts)
+    let _ = writeln!(
+        svg,
+        r##"<line x1="{MARGIN}" y1="{bottom}" x2="{}" y2="{bottom}" stroke="#333333"/>"##,
+        WIDTH - MARGIN
+    );
+    let _ = writeln!(
+        svg,
+        r##"<text x="{}" y="{:.1}" font-size="12" text-anchor="end" fill="#555555">{:.0}W</text>"##,
+        MARGIN - 6,
+        HEADER as f64 + 12.0,
+        top
+    );
+    let tick = if duration > 3600 { 600 } else { 300 };
+    for secs in (0..=duration).step_by(tick) {
+        let _ = writeln!(
+            svg,
+            r##"<text x="{:.1}" y="{}" font-size="12" text-anchor="middle" fill="#555555">{}</text>"##,
+            x(secs),
+            HEIGHT - FOOTER + 18,
+            secs / 60
+        );
+    }
+    let _ = writeln!(
+        svg,
+        r##"<text x="{}" y="{}" font-size="12" text-anchor="end" fill="#555555">min</text>"##,
+        WIDTH - MARGIN,
+        HEIGHT - 6
+    );
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn class_color(intensity_class: &str) -> &'static str {
+    CLASS_COLORS
+        .iter()
+        .find(|(class, _)| class.eq_ignore_ascii_case(intensity_class))
+        .unwrap_or(&CLASS_COLORS[1])
+        .1
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(feature = "png")]
+fn svg_to_png(svg: &str) -> Result<Vec<u8>, String> {
+    use resvg::{tiny_skia, usvg};
+
+    let mut options = usvg::Options::default();
+    options.fontdb_mut().load_system_fonts();
+    let tree = usvg::Tree::from_str(svg, &options).map_err(|e| e.to_string())?;
+    let size = tree.size().to_int_size();
+    let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height())
+        .ok_or_else(|| format!("invalid image size: {size:?}"))?;
+    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+    pixmap.encode_png().map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "png"))]
+fn svg_to_png(_svg: &str) -> Result<Vec<u8>, String> {
+    Err("PNG export needs workout built with the `png` feature".to_string())
+}
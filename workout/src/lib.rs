@@ -0,0 +1,108 @@
+//! Convert FIT workouts, e.g. the training plans of [intervals.icu](https://intervals.icu/),
+//! into iGPS custom workouts and upload them.
+//!
+//! ```no_run
+//! use workout::{build_workout, PowerScale, DEFAULT_STEP_NAME};
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let fit = std::fs::read("W2_5_125.fit")?;
+//! let power_scale = PowerScale {
+//!     source_ftp: Some(250),
+//!     ftp: None,
+//! };
+//! let workout_data = build_workout(fit, &None, power_scale, DEFAULT_STEP_NAME)?;
+//! workout_data.validate()?;
+//! println!("{}", serde_json::to_string(&workout_data)?);
+//! # Ok(())
+//! # }
+//! ```
+use std::str::FromStr;
+
+pub mod api;
+pub mod stats;
+
+#[cfg(test)]
+mod fixtures;
+
+pub use api::utils::{build_workout, load_workout, ConvertError, PowerScale, DEFAULT_STEP_NAME};
+pub use api::{ValidationError, ValidationProblem, WorkoutData};
+
+/// Adjust the power or the duration of every step, e.g. add 10 W to every power target.
+#[derive(Copy, Clone, Debug)]
+pub struct Adjustment {
+    pub operation: Operation,
+    pub target_type: TargetType,
+    pub target_value: u32,
+}
+
+impl Adjustment {
+    /// Apply the adjustment to the power target and the duration (seconds) of one step
+    pub fn apply_operation(&self, min_value: &mut u32, max_value: &mut u32, duration: &mut u32) {
+        match self.target_type {
+            TargetType::Power => {
+                self.apply_to_value(min_value);
+                self.apply_to_value(max_value);
+            }
+            TargetType::Duration => {
+                self.apply_to_value(duration);
+            }
+        }
+    }
+
+    fn apply_to_value(&self, target: &mut u32) {
+        match self.operation {
+            Operation::Add => *target = target.saturating_add(self.target_value),
+            Operation::Subtract => *target = target.saturating_sub(self.target_value),
+            Operation::Multiply => *target = target.saturating_mul(self.target_value),
+            Operation::Divide => {
+                if let Some(v) = target.checked_div(self.target_value) {
+                    *target = v
+                }
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Operation {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl FromStr for Operation {
+    type Err = String;
+
+    /// `add`, `subtract`, `multiply` or `divide`, case is ignored
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "add" => Ok(Operation::Add),
+            "subtract" => Ok(Operation::Subtract),
+            "multiply" => Ok(Operation::Multiply),
+            "divide" => Ok(Operation::Divide),
+            _ => Err(format!(
+                "invalid operation {s:?}, use add, subtract, multiply or divide"
+            )),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum TargetType {
+    Power,
+    Duration,
+}
+
+impl FromStr for TargetType {
+    type Err = String;
+
+    /// `power` or `duration`, case is ignored
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "power" => Ok(TargetType::Power),
+            "duration" => Ok(TargetType::Duration),
+            _ => Err(format!("invalid target type {s:?}, use power or duration")),
+        }
+    }
+}
@@ -1,95 +1,227 @@
-use clap::{Args, Parser, ValueEnum};
-use std::fs;
+use clap::builder::{PossibleValuesParser, RangedU64ValueParser, TypedValueParser};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use config::Config;
+use std::collections::HashSet;
+use std::error::Error;
+use std::io;
+use std::io::{IsTerminal, Read};
 use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+use upload::Account;
+use util::FitSource;
 use walkdir::WalkDir;
+use workout::{api, Adjustment, Operation, PowerScale, TargetType};
 
-mod api;
+mod image;
+mod show;
+mod upload;
 mod util;
 
+/// Adjust the power or the duration of every step, e.g. `add power -v 10`.
+///
+/// The adjustment is optional, but once one of its args is given all of them are required.
+#[derive(Args, Clone, Debug)]
+pub(crate) struct FitWorkoutArgs {
+    #[arg(
+        required = false,
+        requires_all = ["target_type", "target_value"],
+        value_parser = PossibleValuesParser::new(["add", "subtract", "multiply", "divide"])
+            .try_map(|s| s.parse::<Operation>())
+    )]
+    pub(crate) operation: Operation,
+
+    #[arg(
+        required = false,
+        requires = "operation",
+        value_parser = PossibleValuesParser::new(["power", "duration"])
+            .try_map(|s| s.parse::<TargetType>())
+    )]
+    pub(crate) target_type: TargetType,
+
+    #[arg(short = 'v', long, required = false, requires = "operation")]
+    pub(crate) target_value: u32,
+}
+
 #[derive(Parser, Debug)]
-#[command(version, author, about, long_about = None)]
+#[command(version, author, about, long_about = None, subcommand_negates_reqs = true)]
 pub(crate) struct Cli {
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+
     #[clap(flatten)]
     pub(crate) fit_res: FitResource,
 
     #[clap(flatten)]
     pub(crate) fit_workout_args: Option<FitWorkoutArgs>,
 
+    #[clap(flatten)]
+    pub(crate) upload_args: UploadArgs,
+
+    /// Output format, `json` prints one JSON line per processed file
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub(crate) output: OutputFormat,
+
     /// iGPS user token(choose one of token and username/password)
-    #[arg(short, long)]
+    #[arg(short, long, env = "IGPS_TOKEN", hide_env_values = true)]
     pub(crate) token: Option<String>,
 
     /// iGPS user username(choose one of token and username/password)
-    #[arg(short, long)]
+    #[arg(short, long, env = "IGPS_USERNAME")]
     pub(crate) username: Option<String>,
 
     /// iGPS user password(choose one of token and username/password)
-    #[arg(short, long)]
+    #[arg(short, long, env = "IGPS_PASSWORD", hide_env_values = true)]
     pub(crate) password: Option<String>,
+
+    /// Config file, defaults to `~/.config/igps_tools/config.toml`
+    #[arg(long, global = true, env = config::CONFIG_ENV)]
+    pub(crate) config: Option<PathBuf>,
+
+    /// The FTP the fit files are planned with, defaults to `workout.ftp` of the config file
+    #[arg(long, global = true)]
+    pub(crate) ftp: Option<u32>,
+
+    /// Step name template, `{name}`, `{index}` and `{notes}` are filled in; notes left out are
+    /// added to the description. Defaults to `workout.step_name` of the config file or `{name}-{index}`
+    #[arg(long, global = true)]
+    pub(crate) step_name: Option<String>,
+
+    /// Upload to these config file profiles instead, e.g. `--profile alice,bob`
+    #[arg(long, value_delimiter = ',')]
+    pub(crate) profile: Vec<String>,
+}
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum Command {
+    /// Draw the power profile of the workouts in the terminal and list their steps, nothing is uploaded
+    Show(ShowArgs),
+    /// Save the power profile of the workouts as SVG or PNG images, nothing is uploaded
+    Export(ExportArgs),
 }
 
 #[derive(Args, Debug)]
-pub(crate) struct FitWorkoutArgs {
-    #[arg(value_enum)]
-    pub(crate) operation: Operation,
+pub(crate) struct ExportArgs {
+    #[clap(flatten)]
+    pub(crate) fit_res: FitResource,
 
-    #[arg(value_enum)]
-    pub(crate) target_type: TargetType,
+    #[clap(flatten)]
+    pub(crate) fit_workout_args: Option<FitWorkoutArgs>,
 
-    #[arg(short = 'v', long)]
-    pub(crate) target_value: u32,
+    /// Image format, `png` needs the `png` feature
+    #[arg(long, value_enum, default_value_t = image::ImageFormat::Svg)]
+    pub(crate) format: image::ImageFormat,
+
+    /// Where the images are written, defaults to `workout.output_dir` of the config file or `.`
+    #[arg(short, long)]
+    pub(crate) output_dir: Option<PathBuf>,
 }
 
-impl FitWorkoutArgs {
-    pub(crate) fn apply_operation(
-        &self,
-        min_value: &mut u32,
-        max_value: &mut u32,
-        duration: &mut u32,
-    ) {
-        match self.target_type {
-            TargetType::Power => {
-                self.apply_to_value(min_value);
-                self.apply_to_value(max_value);
-            }
-            TargetType::Duration => {
-                self.apply_to_value(duration);
-            }
-        }
-    }
+#[derive(Args, Debug)]
+pub(crate) struct ShowArgs {
+    #[clap(flatten)]
+    pub(crate) fit_res: FitResource,
 
-    fn apply_to_value(&self, target: &mut u32) {
-        match self.operation {
-            Operation::Add => *target = target.saturating_add(self.target_value),
-            Operation::Subtract => *target = target.saturating_sub(self.target_value),
-            Operation::Multiply => *target = target.saturating_mul(self.target_value),
-            Operation::Divide => {
-                if self.target_value != 0 {
-                    *target /= self.target_value
-                }
-            }
-        }
-    }
+    #[clap(flatten)]
+    pub(crate) fit_workout_args: Option<FitWorkoutArgs>,
+
+    /// Columns of the chart
+    #[arg(long, default_value_t = 80)]
+    pub(crate) width: usize,
+
+    /// Rows of the chart
+    #[arg(long, default_value_t = 10)]
+    pub(crate) height: usize,
+
+    /// Draw with `#` instead of Unicode block elements
+    #[arg(long)]
+    pub(crate) ascii: bool,
+
+    /// Don't color the power zones, also off with `NO_COLOR` or when stdout isn't a terminal
+    #[arg(long)]
+    pub(crate) no_color: bool,
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
-enum Operation {
-    Add,
-    Subtract,
-    Multiply,
-    Divide,
+#[derive(Args, Debug)]
+pub(crate) struct UploadArgs {
+    /// Maximum number of workouts uploaded at the same time
+    #[arg(long, default_value_t = 4, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub(crate) concurrency: usize,
+
+    /// Maximum number of upload requests per second(0 means unlimited)
+    #[arg(long, default_value_t = 2.0, value_parser = parse_rate)]
+    pub(crate) rate: f64,
+
+    /// How many times an upload is retried after a 5xx or 429 response or a failed connection.
+    /// A timeout isn't retried, iGPS may have saved the workout already
+    #[arg(long, default_value_t = 3)]
+    pub(crate) retries: u32,
+
+    /// Only convert the workouts and print their duration, TSS, IF, NP, kJ and time in zone
+    #[arg(long)]
+    pub(crate) dry_run: bool,
+
+    /// Append duration, NP, kJ, TSS and IF to the workout description
+    #[arg(long)]
+    pub(crate) stats_description: bool,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
-enum TargetType {
-    Power,
-    Duration,
+pub(crate) enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// `--rate`, 0 or more requests per second, but not so few that the interval between two
+/// doesn't fit in a `Duration`
+fn parse_rate(s: &str) -> Result<f64, String> {
+    let rate: f64 = s.parse().map_err(|e| format!("{e}"))?;
+    if rate.is_nan()
+        || rate < 0.0
+        || (rate > 0.0 && Duration::try_from_secs_f64(1.0 / rate).is_err())
+    {
+        return Err(format!("{s} isn't a valid number of requests per second"));
+    }
+    Ok(rate)
+}
+
+/// Exit codes of `workout`, clap exits with 2 on bad arguments
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Exit {
+    Success = 0,
+    /// At least one workout failed, the others were processed
+    Failed = 1,
+    /// The config file, its adjustment or a `--profile` is invalid
+    Config = 3,
+    /// No token, or the login with username and password failed
+    Login = 4,
+    /// The fit file, folder, zip package or output folder can't be read or written
+    Input = 5,
+}
+
+impl From<Exit> for ExitCode {
+    fn from(exit: Exit) -> Self {
+        ExitCode::from(exit as u8)
+    }
+}
+
+impl Exit {
+    fn from_success(success: bool) -> Self {
+        if success {
+            Exit::Success
+        } else {
+            Exit::Failed
+        }
+    }
 }
 
 #[derive(Args, Debug)]
 #[group(required = true, multiple = false)]
 pub(crate) struct FitResource {
-    /// The path of a single fit file
+    /// The path of a single fit file (or iGPS workout json), `-` reads a fit file or zip package
+    /// from stdin, a http(s) URL downloads it first
     #[arg(short = 'f', long)]
     pub(crate) fit: Option<String>,
 
@@ -102,78 +234,421 @@ pub(crate) struct FitResource {
     pub(crate) fit_zip: Option<String>,
 }
 
-const TMP_FOLDER: &str = "./.tmp";
-
 #[tokio::main]
-async fn main() {
-    let cli = Cli::parse();
-    let token = match (&cli.token, &cli.username, &cli.password) {
-        (Some(t), None, None) => t.clone(),
-        (None, Some(username), Some(password)) => {
-            let response = auth::get_token(username, password).await;
-            match response.access_token {
-                Some(token) => format!("Bearer {}", token),
-                None => {
-                    panic!("Login fail, msg: {:?}", response);
+async fn main() -> ExitCode {
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    // `IGPS_TOKEN` and the other variables may stay set, the profiles bring their own
+    // credentials, but a `--token` on the command line would be silently ignored
+    if let Some(id) = ["token", "username", "password"].into_iter().find(|id| {
+        !cli.profile.is_empty() && matches.value_source(id) == Some(ValueSource::CommandLine)
+    }) {
+        Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                format!(
+                    "--{id} can't be used with --profile, the profiles have their own credentials"
+                ),
+            )
+            .exit();
+    }
+    let config = match Config::load(cli.config.as_deref()) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Err, config: {e}");
+            return Exit::Config.into();
+        }
+    };
+    match &cli.command {
+        Some(Command::Show(show_args)) => return show(&cli, show_args, &config).await,
+        Some(Command::Export(export_args)) => return export(&cli, export_args, &config).await,
+        None => {}
+    }
+    let adjustment = match resolve_adjustment(&cli.fit_workout_args, &config) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("Err, config adjustment: {e}");
+            return Exit::Config.into();
+        }
+    };
+    let accounts = match build_accounts(&cli, &config).await {
+        Ok(accounts) => accounts,
+        Err((exit, e)) => {
+            eprintln!("Err, msg: {e}");
+            return exit.into();
+        }
+    };
+    let step_name = resolve_step_name(&cli, &config);
+    let sources = match resolve_sources(&cli.fit_res).await {
+        Ok(sources) => sources,
+        Err(e) => {
+            eprintln!("Err, msg: {e}");
+            return Exit::Input.into();
+        }
+    };
+
+    let mut reports = Vec::new();
+    for account in &accounts {
+        reports.extend(
+            upload::upload_all(
+                &sources,
+                account,
+                &adjustment,
+                &step_name,
+                &cli.upload_args,
+                cli.output,
+            )
+            .await,
+        );
+    }
+
+    let success = match cli.output {
+        OutputFormat::Text => upload::print_summary(&reports),
+        OutputFormat::Json => reports.iter().all(|r| r.result.is_ok()),
+    };
+    Exit::from_success(success).into()
+}
+
+/// `workout show`, print every workout as a chart and a step list
+async fn show(cli: &Cli, show_args: &ShowArgs, config: &Config) -> ExitCode {
+    let adjustment = match resolve_adjustment(&show_args.fit_workout_args, config) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("Err, config adjustment: {e}");
+            return Exit::Config.into();
+        }
+    };
+    let sources = match resolve_sources(&show_args.fit_res).await {
+        Ok(sources) => sources,
+        Err(e) => {
+            eprintln!("Err, msg: {e}");
+            return Exit::Input.into();
+        }
+    };
+    let power_scale = PowerScale {
+        source_ftp: cli.ftp.or(config.workout.ftp),
+        ftp: None,
+    };
+    let step_name = resolve_step_name(cli, config);
+    let style = show::ChartStyle {
+        width: show_args.width,
+        height: show_args.height,
+        ascii: show_args.ascii,
+        color: !show_args.no_color
+            && std::env::var_os("NO_COLOR").is_none()
+            && io::stdout().is_terminal(),
+    };
+    let mut success = true;
+    for (i, fit_source) in sources.iter().enumerate() {
+        let workout_data = fit_source
+            .read()
+            .map_err(|e| format!("read file fail: {e}"))
+            .and_then(|data| {
+                workout::load_workout(data, &adjustment, power_scale, &step_name)
+                    .map_err(|e| e.to_string())
+            });
+        match workout_data {
+            Ok(workout_data) => {
+                if i > 0 {
+                    println!();
                 }
+                println!("path: {:?}", fit_source.name());
+                print!(
+                    "{}",
+                    show::render(&workout_data, power_scale.athlete_ftp(), &style)
+                );
+            }
+            Err(e) => {
+                eprintln!("path: {:?}, show fail: {e}", fit_source.name());
+                success = false;
             }
         }
-        _ => {
-            panic!("Error: Must use either 'token' or both 'username' and 'password'");
+    }
+    Exit::from_success(success).into()
+}
+
+/// `workout export`, write one image per workout named after its file
+async fn export(cli: &Cli, export_args: &ExportArgs, config: &Config) -> ExitCode {
+    let adjustment = match resolve_adjustment(&export_args.fit_workout_args, config) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("Err, config adjustment: {e}");
+            return Exit::Config.into();
+        }
+    };
+    let sources = match resolve_sources(&export_args.fit_res).await {
+        Ok(sources) => sources,
+        Err(e) => {
+            eprintln!("Err, msg: {e}");
+            return Exit::Input.into();
+        }
+    };
+    let output_dir = export_args
+        .output_dir
+        .clone()
+        .or_else(|| config.workout.output_dir.clone())
+        .unwrap_or_else(|| PathBuf::from("."));
+    if let Err(e) = std::fs::create_dir_all(&output_dir) {
+        eprintln!("Err, create {:?} fail: {e}", output_dir);
+        return Exit::Input.into();
+    }
+    let power_scale = PowerScale {
+        source_ftp: cli.ftp.or(config.workout.ftp),
+        ftp: None,
+    };
+    let step_name = resolve_step_name(cli, config);
+    let mut success = true;
+    let mut taken = HashSet::new();
+    for fit_source in &sources {
+        let name = fit_source.name();
+        let stem = export_stem(&name, &mut taken);
+        let path = output_dir.join(format!("{stem}.{}", export_args.format.extension()));
+        let result = fit_source
+            .read()
+            .map_err(|e| format!("read file fail: {e}"))
+            .and_then(|data| {
+                workout::load_workout(data, &adjustment, power_scale, &step_name)
+                    .map_err(|e| e.to_string())
+            })
+            .and_then(|workout_data| {
+                image::render(&workout_data, power_scale.athlete_ftp(), export_args.format)
+            })
+            .and_then(|data| std::fs::write(&path, data).map_err(|e| e.to_string()));
+        match result {
+            Ok(()) => println!("path: {:?}, image: {:?}", name, path),
+            Err(e) => {
+                eprintln!("path: {:?}, export fail: {e}", name);
+                success = false;
+            }
         }
+    }
+    Exit::from_success(success).into()
+}
+
+/// The file name of an image without extension: the source's, without the query of a URL.
+/// A name an earlier source of the run already took gets ` (2)`, ` (3)`...
+fn export_stem(name: &str, taken: &mut HashSet<String>) -> String {
+    let is_url = name.starts_with("http://") || name.starts_with("https://");
+    let file_name = name.rsplit('/').next().unwrap_or(name);
+    let file_name = match file_name.find(['?', '#']) {
+        Some(end) if is_url => &file_name[..end],
+        _ => file_name,
     };
+    let stem = Path::new(file_name)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| "workout".to_string());
+    // case-insensitive file systems treat `A` and `a` as the same file
+    let mut unique = stem.clone();
+    let mut n = 1;
+    while !taken.insert(unique.to_lowercase()) {
+        n += 1;
+        unique = format!("{stem} ({n})");
+    }
+    unique
+}
+
+fn resolve_step_name(cli: &Cli, config: &Config) -> String {
+    cli.step_name
+        .clone()
+        .or_else(|| config.workout.step_name.clone())
+        .unwrap_or_else(|| workout::DEFAULT_STEP_NAME.to_string())
+}
+
+/// The adjustment of the command line, otherwise the one of the config file
+fn resolve_adjustment(
+    fit_workout_args: &Option<FitWorkoutArgs>,
+    config: &Config,
+) -> Result<Option<Adjustment>, String> {
+    match (fit_workout_args, &config.workout.adjustment) {
+        (Some(args), _) => Ok(Some(Adjustment {
+            operation: args.operation,
+            target_type: args.target_type,
+            target_value: args.target_value,
+        })),
+        (None, Some(adjustment)) => Ok(Some(Adjustment {
+            operation: adjustment.operation.parse()?,
+            target_type: adjustment.target_type.parse()?,
+            target_value: adjustment.target_value,
+        })),
+        (None, None) => Ok(None),
+    }
+}
 
-    match &cli.fit_res {
+async fn resolve_sources(fit_res: &FitResource) -> Result<Vec<FitSource>, Box<dyn Error>> {
+    match fit_res {
         FitResource {
             fit: Some(fit_path),
             ..
-        } => {
-            let path = Path::new(&fit_path).to_path_buf();
-            if is_fit(&path) {
-                do_push_fit(path, token, &cli.fit_workout_args).await;
-            } else {
-                panic!("{:?} it's not a fit file!", path);
-            }
-        }
+        } => read_fit(fit_path).await,
         FitResource {
             fit_folder: Some(folder_path),
             ..
-        } => {
-            do_push_fit_folder(folder_path, token, &cli.fit_workout_args).await;
-        }
+        } => Ok(find_fit_files(folder_path)
+            .into_iter()
+            .map(FitSource::File)
+            .collect()),
         FitResource {
             fit_zip: Some(zip_path),
             ..
-        } => {
-            match util::unzip_file(zip_path, TMP_FOLDER) {
-                Ok(_) => {
-                    do_push_fit_folder(&TMP_FOLDER.to_string(), token, &cli.fit_workout_args).await;
-                }
-                Err(e) => {
-                    eprintln!("Err, msg: {e}");
-                }
+        } => util::read_zip_file(zip_path),
+        _ => unreachable!(),
+    }
+}
+
+/// One account per `--profile`, otherwise the default one.
+///
+/// Command line and environment credentials win over the ones of the config file.
+async fn build_accounts(cli: &Cli, config: &Config) -> Result<Vec<Account>, (Exit, String)> {
+    let api_url = config
+        .igps
+        .workout_url
+        .clone()
+        .unwrap_or_else(|| api::API_EDIT_CUSTOM_WORKOUT_URL.to_string());
+    let token_url = config
+        .igps
+        .token_url
+        .as_deref()
+        .unwrap_or(auth::API_TOKEN_URL);
+    let source_ftp = cli.ftp.or(config.workout.ftp);
+
+    if cli.profile.is_empty() {
+        let (token, username, password) = credentials(cli, config);
+        let token = if cli.upload_args.dry_run {
+            String::new()
+        } else {
+            login(token, username, password, token_url)
+                .await
+                .map_err(|e| (Exit::Login, e))?
+        };
+        return Ok(vec![Account {
+            profile: None,
+            api_url,
+            token,
+            power_scale: PowerScale {
+                source_ftp,
+                ftp: None,
+            },
+        }]);
+    }
+
+    let mut accounts = Vec::new();
+    for name in &cli.profile {
+        let profile = config.profiles.get(name).ok_or_else(|| {
+            (
+                Exit::Config,
+                format!("profile {name:?} is not in the config file"),
+            )
+        })?;
+        if profile.ftp.is_some() && source_ftp.is_none() {
+            // absolute watts can't be scaled to the profile's FTP without it
+            return Err((
+                Exit::Config,
+                format!(
+                    "profile {name:?} has an ftp, but the FTP the workouts were planned with is missing, set --ftp or workout.ftp"
+                ),
+            ));
+        }
+        let token = if cli.upload_args.dry_run {
+            String::new()
+        } else {
+            login(
+                &profile.token,
+                &profile.username,
+                &profile.password,
+                token_url,
+            )
+            .await
+            .map_err(|e| (Exit::Login, format!("profile {name:?}: {e}")))?
+        };
+        accounts.push(Account {
+            profile: Some(name.clone()),
+            api_url: api_url.clone(),
+            token,
+            power_scale: PowerScale {
+                source_ftp,
+                ftp: profile.ftp,
+            },
+        });
+    }
+    Ok(accounts)
+}
+
+async fn login(
+    token: &Option<String>,
+    username: &Option<String>,
+    password: &Option<String>,
+    token_url: &str,
+) -> Result<String, String> {
+    match (token, username, password) {
+        (Some(t), None, None) => Ok(t.clone()),
+        (None, Some(username), Some(password)) => {
+            let response = auth::get_token(token_url, username, password)
+                .await
+                .map_err(|e| format!("login fail: {e}"))?;
+            match response.access_token {
+                Some(token) => Ok(format!("Bearer {}", token)),
+                None => Err(format!(
+                    "login fail, msg: {}",
+                    response
+                        .error_description
+                        .or(response.error)
+                        .unwrap_or_default()
+                )),
             }
-            fs::remove_dir_all(TMP_FOLDER).unwrap();
         }
-        _ => unreachable!(),
+        _ => Err("must use either 'token' or both 'username' and 'password'".to_string()),
     }
 }
 
-async fn do_push_fit(fit_path: PathBuf, token: String, fit_workout_args: &Option<FitWorkoutArgs>) {
-    let p = fit_path.clone().to_path_buf();
-    let fit_file = fs::read(fit_path).unwrap();
-    let workout_json = api::utils::build_workout_json(fit_file, fit_workout_args);
-    let res = api::push_to_igps(workout_json, token).await;
-    let status = res.status();
-    let res = res.text().await.unwrap();
-    println!("path: {:?}, response status: {:?}, body: {res}", p, status);
+/// The credentials of the default account. The command line and the environment variables
+/// replace all of the config file's, a token from one and a password from the other don't mix.
+fn credentials<'a>(
+    cli: &'a Cli,
+    config: &'a Config,
+) -> (&'a Option<String>, &'a Option<String>, &'a Option<String>) {
+    if cli.token.is_some() || cli.username.is_some() || cli.password.is_some() {
+        (&cli.token, &cli.username, &cli.password)
+    } else {
+        (
+            &config.igps.token,
+            &config.igps.username,
+            &config.igps.password,
+        )
+    }
 }
 
-async fn do_push_fit_folder(
-    fit_folder: &String,
-    token: String,
-    fit_workout_args: &Option<FitWorkoutArgs>,
-) {
+/// Resolve `--fit`, which is either `-` for stdin, a http(s) URL or a local path.
+async fn read_fit(fit_path: &str) -> Result<Vec<FitSource>, Box<dyn Error>> {
+    read_fit_from(fit_path, io::stdin()).await
+}
+
+/// [`read_fit`], `-` reads `stdin`
+async fn read_fit_from(
+    fit_path: &str,
+    mut stdin: impl Read,
+) -> Result<Vec<FitSource>, Box<dyn Error>> {
+    if fit_path == "-" {
+        let mut data = Vec::new();
+        stdin.read_to_end(&mut data)?;
+        return util::read_fit_bytes("stdin", data);
+    }
+    if fit_path.starts_with("http://") || fit_path.starts_with("https://") {
+        let data = api::download(fit_path).await?;
+        return util::read_fit_bytes(fit_path, data);
+    }
+    let path = Path::new(fit_path).to_path_buf();
+    if is_fit(&path) || (path.is_file() && util::has_extension(&path, "json")) {
+        Ok(vec![FitSource::File(path)])
+    } else if path.is_file() && util::has_extension(&path, "zip") {
+        util::read_zip_file(fit_path)
+    } else {
+        Err(format!("{:?} it's not a fit file!", path).into())
+    }
+}
+
+fn find_fit_files(fit_folder: &str) -> Vec<PathBuf> {
     let fit_folder = Path::new(&fit_folder);
     let mut fit_folder_vec: Vec<PathBuf> = Vec::new();
     for entry in WalkDir::new(fit_folder) {
@@ -196,12 +671,146 @@ async fn do_push_fit_folder(
     }
 
     fit_folder_vec.sort_by(|a, b| a.to_string_lossy().cmp(&b.to_string_lossy()));
+    fit_folder_vec
+}
 
-    for path in fit_folder_vec {
-        do_push_fit(path, token.clone(), fit_workout_args).await;
-    }
+fn is_fit(path: &Path) -> bool {
+    path.is_file() && util::has_extension(path, "fit")
 }
 
-fn is_fit(path_buf: &PathBuf) -> bool {
-    path_buf.is_file() && path_buf.extension().and_then(|s| s.to_str()) == Some("fit")
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::io::Cursor;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const W2_5_125: &[u8] = include_bytes!("../examples/W2_5_125.fit");
+
+    /// The tests run in parallel, but `IGPS_TOKEN` is shared by the whole process
+    static ENV: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    fn token(args: &[&str], env_token: Option<&str>, config: &Config) -> Option<String> {
+        let _env = ENV.lock().unwrap();
+        match env_token {
+            Some(token) => env::set_var("IGPS_TOKEN", token),
+            None => env::remove_var("IGPS_TOKEN"),
+        }
+        let cli = Cli::try_parse_from([&["workout", "-f", "a.fit"], args].concat());
+        env::remove_var("IGPS_TOKEN");
+        credentials(&cli.unwrap(), config).0.clone()
+    }
+
+    #[test]
+    fn the_command_line_beats_the_environment_beats_the_config_file() {
+        let config = Config {
+            igps: config::IGPSConfig {
+                token: Some("file".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let cli = Some("cli".to_string());
+        assert_eq!(token(&["-t", "cli"], Some("env"), &config), cli);
+        assert_eq!(token(&[], Some("env"), &config), Some("env".to_string()));
+        assert_eq!(token(&[], None, &config), Some("file".to_string()));
+        assert_eq!(token(&[], None, &Config::default()), None);
+    }
+
+    #[test]
+    fn credentials_from_the_command_line_replace_all_of_the_config_file() {
+        let config = Config {
+            igps: config::IGPSConfig {
+                token: Some("file".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(token(&["-u", "alice", "-p", "secret"], None, &config), None);
+    }
+
+    /// Answer one GET request with `status` and `body`, returns the URL
+    async fn serve(status: u16, body: &'static [u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/W2_5_125.fit", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = socket.read(&mut buf).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..n]);
+            }
+            let head = format!(
+                "HTTP/1.1 {status} X\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                body.len()
+            );
+            socket.write_all(head.as_bytes()).await.unwrap();
+            socket.write_all(body).await.unwrap();
+        });
+        url
+    }
+
+    #[test]
+    fn export_stems_are_unique() {
+        let mut taken = HashSet::new();
+        assert_eq!(export_stem("plans/a.fit", &mut taken), "a");
+        assert_eq!(export_stem("plan.zip/week 1/A.fit", &mut taken), "A (2)");
+        assert_eq!(export_stem("plan.zip/week 2/a.fit", &mut taken), "a (3)");
+        assert_eq!(export_stem("b.json", &mut taken), "b");
+    }
+
+    #[test]
+    fn export_stems_leave_out_the_query() {
+        let mut taken = HashSet::new();
+        let url = "https://intervals.icu/api/v1/a.fit?token=secret&v=1.2";
+        assert_eq!(export_stem(url, &mut taken), "a");
+        assert_eq!(
+            export_stem("https://example.com/download?id=3", &mut taken),
+            "download"
+        );
+        assert_eq!(export_stem("https://example.com/", &mut taken), "workout");
+        // a file may have a `?` in its name
+        assert_eq!(export_stem("what?.fit", &mut taken), "what?");
+    }
+
+    #[tokio::test]
+    async fn a_fit_file_is_read_from_stdin() {
+        let sources = read_fit_from("-", Cursor::new(W2_5_125)).await.unwrap();
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].name(), "stdin");
+        assert_eq!(sources[0].read().unwrap(), W2_5_125);
+    }
+
+    #[tokio::test]
+    async fn stdin_without_a_fit_file_is_an_error() {
+        assert!(read_fit_from("-", Cursor::new(b"hello")).await.is_err());
+        assert!(read_fit_from("-", io::empty()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn a_fit_file_is_downloaded() {
+        let url = serve(200, W2_5_125).await;
+        let sources = read_fit_from(&url, io::empty()).await.unwrap();
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].name(), url);
+        assert_eq!(sources[0].read().unwrap(), W2_5_125);
+    }
+
+    #[tokio::test]
+    async fn an_error_status_is_an_error() {
+        let url = serve(404, b"not found").await;
+        let err = read_fit_from(&url, io::empty()).await.unwrap_err();
+        assert!(err.to_string().contains("404"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn a_page_instead_of_a_fit_file_is_an_error() {
+        let url = serve(200, b"<html>log in</html>").await;
+        assert!(read_fit_from(&url, io::empty()).await.is_err());
+    }
 }
@@ -0,0 +1,285 @@
+use std::fmt::Write;
+use workout::api::{WorkoutData, WorkoutDataStructure};
+use workout::stats::{self, ZONES};
+
+/// Block elements from empty to full, one per eighth of a row
+const BLOCKS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// ANSI colors of `Z1`..`Z7`: grey, blue, green, yellow, orange, red, magenta
+const ZONE_COLORS: [&str; 7] = ["90", "34", "32", "33", "38;5;208", "31", "35"];
+
+/// The y axis labels are padded to this width
+const AXIS_WIDTH: usize = 6;
+
+/// How the chart is drawn
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ChartStyle {
+    /// Columns of the chart, the y axis not included
+    pub(crate) width: usize,
+    /// Rows of the chart
+    pub(crate) height: usize,
+    /// `#` instead of the Unicode block elements
+    pub(crate) ascii: bool,
+    /// Color the columns by power zone, needs the FTP
+    pub(crate) color: bool,
+}
+
+/// Title, statistics, power chart and step list of `workout_data`
+pub(crate) fn render(workout_data: &WorkoutData, ftp: Option<u32>, style: &ChartStyle) -> String {
+    let ftp = ftp.filter(|f| *f > 0);
+    let stats = stats::calculate(&workout_data.structure, ftp);
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "{} ({})  {}",
+        workout_data.title,
+        workout_data.workout_type,
+        stats.describe()
+    );
+    if let Some(zones) = stats.describe_zones() {
+        let _ = writeln!(out, "{zones}");
+    }
+    if !workout_data.description.is_empty() {
+        let _ = writeln!(out, "{}", workout_data.description);
+    }
+    out.push('\n');
+    render_chart(&mut out, workout_data, ftp, style);
+    out.push('\n');
+    let _ = writeln!(
+        out,
+        "{:>3}  {:<24} {:>8}  {:<12} ZONE",
+        "#", "STEP", "DURATION", "TARGET"
+    );
+    let mut index = 0;
+    render_steps(
+        &mut out,
+        &workout_data.structure,
+        &workout_data.workout_type,
+        0,
+        &mut index,
+        ftp,
+        style,
+    );
+    out
+}
+
+fn render_chart(
+    out: &mut String,
+    workout_data: &WorkoutData,
+    ftp: Option<u32>,
+    style: &ChartStyle,
+) {
+    let steps: Vec<(u32, u32)> = stats::expand(&workout_data.structure)
+        .into_iter()
+        .filter(|(secs, _)| *secs > 0)
+        .collect();
+    // the second each step ends at
+    let ends: Vec<u64> = steps
+        .iter()
+        .scan(0_u64, |end, (secs, _)| {
+            *end += *secs as u64;
+            Some(*end)
+        })
+        .collect();
+    let total = ends.last().copied().unwrap_or(0);
+    if total == 0 {
+        let _ = writeln!(out, "(no steps)");
+        return;
+    }
+    if steps.iter().all(|(_, watts)| *watts == 0) {
+        // e.g. a run with pace targets
+        let _ = writeln!(out, "(no power targets)");
+        return;
+    }
+    // every column shows the highest target of its seconds, so short intervals stay visible
+    let width = (style.width.max(1) as u64).min(total) as usize;
+    let mut first = 0;
+    let columns: Vec<u32> = (0..width as u64)
+        .map(|c| {
+            let start = c * total / width as u64;
+            let end = ((c + 1) * total / width as u64).max(start + 1);
+            while ends[first] <= start {
+                first += 1;
+            }
+            let mut watts = 0;
+            for (&(_, step_watts), &step_end) in steps[first..].iter().zip(&ends[first..]) {
+                watts = watts.max(step_watts);
+                if step_end >= end {
+                    break;
+                }
+            }
+            watts
+        })
+        .collect();
+    let top = columns.iter().copied().max().unwrap_or(0).max(1);
+    let height = style.height.max(1);
+
+    for row in (0..height).rev() {
+        let label = match row {
+            r if r == height - 1 => format!("{top}W"),
+            0 => "0W".to_string(),
+            _ => String::new(),
+        };
+        let _ = write!(out, "{:>AXIS_WIDTH$} │", label);
+        for watts in &columns {
+            // eighths of a row this column reaches above the bottom of `row`
+            let filled = (*watts as u64 * height as u64 * 8 / top as u64)
+                .saturating_sub(row as u64 * 8)
+                .min(8) as usize;
+            let cell = if style.ascii {
+                if filled >= 4 {
+                    '#'
+                } else {
+                    ' '
+                }
+            } else {
+                BLOCKS[filled]
+            };
+            match (ftp, style.color && cell != ' ') {
+                (Some(ftp), true) => {
+                    out.push_str(&paint(&cell.to_string(), stats::zone(*watts, ftp)));
+                }
+                _ => out.push(cell),
+            }
+        }
+        out.push('\n');
+    }
+    let end = stats::format_duration(total.min(u32::MAX as u64) as u32);
+    let _ = writeln!(out, "{:>AXIS_WIDTH$} └{}", "", "─".repeat(width));
+    let _ = writeln!(
+        out,
+        "{:>AXIS_WIDTH$}  {:<w$}{}",
+        "",
+        "0:00:00",
+        end,
+        w = width.saturating_sub(end.len())
+    );
+}
+
+fn render_steps(
+    out: &mut String,
+    structure: &[WorkoutDataStructure],
+    workout_type: &str,
+    depth: usize,
+    index: &mut usize,
+    ftp: Option<u32>,
+    style: &ChartStyle,
+) {
+    for item in structure {
+        *index += 1;
+        let name = format!("{}{}", "  ".repeat(depth), item.name);
+        match &item.steps {
+            Some(steps) => {
+                let _ = writeln!(
+                    out,
+                    "{:>3}  {:<24} {:>8}",
+                    index,
+                    name,
+                    format!("{}x", item.length.value)
+                );
+                render_steps(out, steps, workout_type, depth + 1, index, ftp, style);
+            }
+            None => {
+                let watts = stats::target_watts(item);
+                let zone = match ftp {
+                    Some(ftp) if watts > 0 => {
+                        let zone = stats::zone(watts, ftp);
+                        let percent = format!("{} {}%", ZONES[zone].0, watts * 100 / ftp);
+                        if style.color {
+                            paint(&percent, zone)
+                        } else {
+                            percent
+                        }
+                    }
+                    _ => String::new(),
+                };
+                let _ = writeln!(
+                    out,
+                    "{:>3}  {:<24} {:>8}  {:<12} {}",
+                    index,
+                    name,
+                    stats::format_length(item),
+                    describe_target(item, workout_type),
+                    zone
+                );
+            }
+        }
+    }
+}
+
+fn describe_target(item: &WorkoutDataStructure, workout_type: &str) -> String {
+    match &item.intensity_target {
+        Some(target) if target.unit == "PaceCustom" => {
+            let per = if workout_type == "swim" { "100m" } else { "km" };
+            let pace = |secs: Option<u32>| {
+                secs.map(|s| format!("{}:{:02}", s / 60, s % 60))
+                    .unwrap_or_default()
+            };
+            format!(
+                "{}-{}/{per}",
+                pace(target.min_value),
+                pace(target.max_value)
+            )
+        }
+        Some(target) => match (target.min_value, target.max_value) {
+            (Some(min), Some(max)) if min != max => format!("{min}-{max}W"),
+            (Some(v), _) | (None, Some(v)) => format!("{v}W"),
+            (None, None) => format!("{}W", target.value),
+        },
+        None => "-".to_string(),
+    }
+}
+
+fn paint(text: &str, zone: usize) -> String {
+    format!("\x1b[{}m{text}\x1b[0m", ZONE_COLORS[zone])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use workout::{build_workout, PowerScale, DEFAULT_STEP_NAME};
+
+    const W2_5_125: &[u8] = include_bytes!("../examples/W2_5_125.fit");
+
+    const STYLE: ChartStyle = ChartStyle {
+        width: 20,
+        height: 4,
+        ascii: true,
+        color: false,
+    };
+
+    fn chart(workout_data: &WorkoutData) -> Vec<String> {
+        let mut out = String::new();
+        render_chart(&mut out, workout_data, None, &STYLE);
+        out.lines().map(str::to_string).collect()
+    }
+
+    fn w2_5_125() -> WorkoutData {
+        build_workout(
+            W2_5_125.to_vec(),
+            &None,
+            PowerScale::default(),
+            DEFAULT_STEP_NAME,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn a_column_shows_the_highest_target_of_its_seconds() {
+        // 68 minutes in 20 columns, every column holds a part of an interval
+        let lines = chart(&w2_5_125());
+        assert_eq!(lines.len(), 4 + 2);
+        assert_eq!(lines[0], "  179W │ ################## ");
+        assert!(lines[5].ends_with("1:08:00"), "{}", lines[5]);
+    }
+
+    #[test]
+    fn a_long_workout_is_drawn_without_expanding_its_seconds() {
+        let mut workout_data = w2_5_125();
+        workout_data.structure[0].length.value = u32::MAX;
+        let lines = chart(&workout_data);
+        assert_eq!(lines.len(), 4 + 2);
+        // the intervals are in the last column
+        assert!(lines[0].ends_with("  #"), "{}", lines[0]);
+    }
+}
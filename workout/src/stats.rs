@@ -0,0 +1,426 @@
+//! Duration, NP, kJ, TSS, IF and time in zone of a workout, calculated from its targets
+use crate::api::WorkoutDataStructure;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt::Write;
+
+/// Coggan power zones, the upper bound of each zone in % FTP
+pub const ZONES: [(&str, u32); 7] = [
+    ("Z1", 55),
+    ("Z2", 75),
+    ("Z3", 90),
+    ("Z4", 105),
+    ("Z5", 120),
+    ("Z6", 150),
+    ("Z7", u32::MAX),
+];
+
+/// NP averages the power over a rolling window of this many seconds.
+const NP_WINDOW: usize = 30;
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct WorkoutStats {
+    /// Seconds, repetitions expanded
+    pub duration: u32,
+    pub avg_power: f64,
+    pub normalized_power: f64,
+    pub kilojoules: f64,
+    pub intensity_factor: Option<f64>,
+    pub tss: Option<f64>,
+    /// Seconds spent in each of `Z1`..`Z7`
+    pub time_in_zone: Option<[u32; 7]>,
+}
+
+/// One expanded step: seconds and target watts (middle of the min/max target)
+pub fn expand(structure: &[WorkoutDataStructure]) -> Vec<(u32, u32)> {
+    expand_steps(structure)
+        .into_iter()
+        .map(|item| (step_seconds(item), target_watts(item)))
+        .collect()
+}
+
+/// The steps in the order they're ridden, repetitions expanded
+pub fn expand_steps(structure: &[WorkoutDataStructure]) -> Vec<&WorkoutDataStructure> {
+    let mut steps = Vec::new();
+    expand_into(structure, &mut steps);
+    steps
+}
+
+fn expand_into<'a>(
+    structure: &'a [WorkoutDataStructure],
+    steps: &mut Vec<&'a WorkoutDataStructure>,
+) {
+    for item in structure {
+        match &item.steps {
+            Some(children) if item.length.unit == "Repetition" => {
+                for _ in 0..item.length.value {
+                    expand_into(children, steps);
+                }
+            }
+            Some(children) => expand_into(children, steps),
+            None => steps.push(item),
+        }
+    }
+}
+
+/// Seconds of the whole workout, every repetition counted
+pub fn total_duration(structure: &[WorkoutDataStructure]) -> u32 {
+    structure.iter().fold(0, |sum: u32, item| {
+        let secs = match &item.steps {
+            Some(children) => total_duration(children).saturating_mul(repetitions(item)),
+            None => step_seconds(item),
+        };
+        sum.saturating_add(secs)
+    })
+}
+
+/// How often the children of `item` are ridden, 1 for a block that isn't a repetition
+pub fn repetitions(item: &WorkoutDataStructure) -> u32 {
+    if item.length.unit == "Repetition" {
+        item.length.value
+    } else {
+        1
+    }
+}
+
+/// Seconds and joules of the whole workout, every repetition counted. Unlike
+/// [`total_duration`] the seconds don't stop at `u32::MAX`.
+fn total_work(structure: &[WorkoutDataStructure]) -> (f64, f64) {
+    structure
+        .iter()
+        .map(|item| match &item.steps {
+            Some(children) => {
+                let (secs, joules) = total_work(children);
+                let count = repetitions(item) as f64;
+                (secs * count, joules * count)
+            }
+            None => {
+                let secs = step_seconds(item) as f64;
+                (secs, secs * target_watts(item) as f64)
+            }
+        })
+        .fold((0.0, 0.0), |(secs, joules), step| {
+            (secs + step.0, joules + step.1)
+        })
+}
+
+/// Seconds spent in each zone, every repetition counted
+fn time_in_zone(structure: &[WorkoutDataStructure], ftp: u32) -> [u32; 7] {
+    let mut time_in_zone = [0_u32; 7];
+    for item in structure {
+        match &item.steps {
+            Some(children) => {
+                let block = self::time_in_zone(children, ftp);
+                for (time, secs) in time_in_zone.iter_mut().zip(block) {
+                    *time = time.saturating_add(secs.saturating_mul(repetitions(item)));
+                }
+            }
+            None => {
+                let time = &mut time_in_zone[zone(target_watts(item), ftp)];
+                *time = time.saturating_add(step_seconds(item));
+            }
+        }
+    }
+    time_in_zone
+}
+
+/// Seconds of a step, 0 for distance steps as their time depends on the athlete
+pub fn step_seconds(item: &WorkoutDataStructure) -> u32 {
+    match item.length.unit.as_str() {
+        "Meter" => 0,
+        _ => item.length.value,
+    }
+}
+
+/// The length of a step, `h:mm:ss` or meters for distance steps
+pub fn format_length(item: &WorkoutDataStructure) -> String {
+    match item.length.unit.as_str() {
+        "Meter" => format!("{}m", item.length.value),
+        _ => format_duration(item.length.value),
+    }
+}
+
+/// Target watts of a step, the middle of its min/max target, 0 for pace targets
+pub fn target_watts(item: &WorkoutDataStructure) -> u32 {
+    match &item.intensity_target {
+        Some(target) if target.unit.starts_with("Power") => {
+            match (target.min_value, target.max_value) {
+                (Some(min), Some(max)) => ((min as u64 + max as u64) / 2) as u32,
+                (Some(v), None) | (None, Some(v)) => v,
+                (None, None) => target.value,
+            }
+        }
+        _ => 0,
+    }
+}
+
+/// The statistics of a workout, IF, TSS and time in zone need the athlete's `ftp`
+pub fn calculate(structure: &[WorkoutDataStructure], ftp: Option<u32>) -> WorkoutStats {
+    let duration = total_duration(structure);
+    if duration == 0 {
+        return WorkoutStats::default();
+    }
+    let (secs, joules) = total_work(structure);
+    let avg_power = joules / secs;
+    let normalized_power = normalized_power(structure).unwrap_or(avg_power);
+
+    let mut stats = WorkoutStats {
+        duration,
+        avg_power,
+        normalized_power,
+        kilojoules: joules / 1000.0,
+        ..Default::default()
+    };
+    if let Some(ftp) = ftp.filter(|f| *f > 0) {
+        let intensity_factor = normalized_power / ftp as f64;
+        stats.intensity_factor = Some(intensity_factor);
+        stats.tss =
+            Some(duration as f64 * normalized_power * intensity_factor / (ftp as f64 * 36.0));
+        stats.time_in_zone = Some(time_in_zone(structure, ftp));
+    }
+    stats
+}
+
+/// Index into `ZONES` of `watts`, `ftp` must not be 0
+pub fn zone(watts: u32, ftp: u32) -> usize {
+    let percent = watts as u64 * 100 / ftp as u64;
+    ZONES
+        .iter()
+        .position(|(_, upper)| percent < *upper as u64)
+        .unwrap_or(ZONES.len() - 1)
+}
+
+/// 4th root of the mean of the 4th power of the 30s rolling average.
+///
+/// Only the first 30 seconds of a step change the rolling average, the rest of the step
+/// averages to its own watts and is added at once.
+fn normalized_power(structure: &[WorkoutDataStructure]) -> Option<f64> {
+    let mut np = RollingAverage::default();
+    np.add_steps(structure);
+    (np.count > 0.0).then(|| (np.sum / np.count).powf(0.25))
+}
+
+/// The state of the NP calculation, the steps are added in the order they're ridden
+#[derive(Default)]
+struct RollingAverage {
+    window: VecDeque<f64>,
+    window_sum: f64,
+    /// Sum of the 4th power of the rolling averages
+    sum: f64,
+    /// Number of rolling averages, a float as repetitions may add more than a u64 holds
+    count: f64,
+}
+
+impl RollingAverage {
+    fn add_steps(&mut self, structure: &[WorkoutDataStructure]) {
+        for item in structure {
+            match &item.steps {
+                Some(children) => {
+                    let count = repetitions(item);
+                    let block_secs = total_duration(children) as usize;
+                    if count == 0 || block_secs == 0 {
+                        continue;
+                    }
+                    // once a whole window of the block has been ridden every further repetition
+                    // starts from the same window and adds the same, it's only calculated once
+                    let warm_up = NP_WINDOW.div_ceil(block_secs) as u32;
+                    for _ in 0..count.min(warm_up) {
+                        self.add_steps(children);
+                    }
+                    if count > warm_up {
+                        let (sum, samples) = (self.sum, self.count);
+                        self.add_steps(children);
+                        let repeated = (count - warm_up - 1) as f64;
+                        self.sum += (self.sum - sum) * repeated;
+                        self.count += (self.count - samples) * repeated;
+                    }
+                }
+                None => self.add_step(step_seconds(item), target_watts(item)),
+            }
+        }
+    }
+
+    fn add_step(&mut self, secs: u32, watts: u32) {
+        let watts = watts as f64;
+        let changing = (secs as usize).min(NP_WINDOW);
+        for _ in 0..changing {
+            if self.window.len() == NP_WINDOW {
+                self.window_sum -= self.window.pop_front().unwrap_or(0.0);
+            }
+            self.window.push_back(watts);
+            self.window_sum += watts;
+            if self.window.len() == NP_WINDOW {
+                self.sum += (self.window_sum / NP_WINDOW as f64).powi(4);
+                self.count += 1.0;
+            }
+        }
+        let steady = (secs as usize - changing) as f64;
+        self.sum += steady * watts.powi(4);
+        self.count += steady;
+    }
+}
+
+/// `H:MM:SS`
+pub fn format_duration(secs: u32) -> String {
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+impl WorkoutStats {
+    /// One line summary, also used as the workout description
+    pub fn describe(&self) -> String {
+        let mut s = format!(
+            "Duration {}, NP {:.0}W, {:.0}kJ",
+            format_duration(self.duration),
+            self.normalized_power,
+            self.kilojoules
+        );
+        if let (Some(tss), Some(intensity_factor)) = (self.tss, self.intensity_factor) {
+            let _ = write!(s, ", TSS {:.0}, IF {:.2}", tss, intensity_factor);
+        }
+        s
+    }
+
+    /// Time in every zone ridden, e.g. `Z1 0:28:00, Z2 0:40:00`
+    pub fn describe_zones(&self) -> Option<String> {
+        let time_in_zone = self.time_in_zone?;
+        let zones: Vec<String> = ZONES
+            .iter()
+            .zip(time_in_zone)
+            .filter(|(_, secs)| *secs > 0)
+            .map(|((name, _), secs)| format!("{} {}", name, format_duration(secs)))
+            .collect();
+        Some(zones.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{repeat, step};
+
+    /// NP from one watt value per second, the way it's defined
+    fn reference_np(steps: &[(u32, u32)]) -> f64 {
+        let series: Vec<f64> = steps
+            .iter()
+            .flat_map(|(secs, watts)| std::iter::repeat_n(*watts as f64, *secs as usize))
+            .collect();
+        let averages: Vec<f64> = series
+            .windows(NP_WINDOW)
+            .map(|w| (w.iter().sum::<f64>() / NP_WINDOW as f64).powi(4))
+            .collect();
+        (averages.iter().sum::<f64>() / averages.len() as f64).powf(0.25)
+    }
+
+    #[test]
+    fn total_duration_counts_every_repetition() {
+        let structure = vec![
+            step(600, 150, 150),
+            repeat(3, vec![step(300, 250, 250), step(120, 100, 100)]),
+            step(300, 120, 120),
+        ];
+        assert_eq!(total_duration(&structure), 600 + 3 * 420 + 300);
+        let nested = vec![repeat(2, vec![repeat(3, vec![step(10, 100, 100)])])];
+        assert_eq!(total_duration(&nested), 60);
+    }
+
+    #[test]
+    fn total_duration_leaves_out_distance_steps() {
+        let mut distance = step(5000, 200, 200);
+        distance.length.unit = "Meter".to_string();
+        assert_eq!(total_duration(&[step(600, 150, 150), distance]), 600);
+    }
+
+    #[test]
+    fn calculate_constant_power_at_ftp() {
+        let stats = calculate(&[step(3600, 200, 200)], Some(200));
+        assert_eq!(stats.duration, 3600);
+        assert!((stats.avg_power - 200.0).abs() < 1e-9);
+        assert!((stats.normalized_power - 200.0).abs() < 1e-9);
+        assert!((stats.kilojoules - 720.0).abs() < 1e-9);
+        assert!((stats.intensity_factor.unwrap() - 1.0).abs() < 1e-9);
+        assert!((stats.tss.unwrap() - 100.0).abs() < 1e-6);
+        assert_eq!(stats.time_in_zone, Some([0, 0, 0, 3600, 0, 0, 0]));
+    }
+
+    #[test]
+    fn calculate_intervals() {
+        let structure = vec![
+            step(600, 150, 150),
+            repeat(5, vec![step(45, 400, 400), step(15, 100, 100)]),
+            step(600, 120, 120),
+        ];
+        let stats = calculate(&structure, Some(250));
+        assert_eq!(stats.duration, 1500);
+        let expected = reference_np(&expand(&structure));
+        assert!((stats.normalized_power - expected).abs() < 1e-6);
+        assert!(stats.normalized_power > stats.avg_power);
+        let time_in_zone = stats.time_in_zone.unwrap();
+        assert_eq!(time_in_zone.iter().sum::<u32>(), 1500);
+        assert_eq!(time_in_zone[6], 5 * 45);
+    }
+
+    #[test]
+    fn calculate_repetitions_shorter_than_the_np_window() {
+        let structure = vec![
+            step(120, 150, 150),
+            repeat(
+                3,
+                vec![
+                    repeat(20, vec![step(10, 400, 400), step(5, 100, 100)]),
+                    step(300, 120, 120),
+                ],
+            ),
+            repeat(40, vec![step(1, 300, 300), step(2, 0, 0)]),
+        ];
+        let stats = calculate(&structure, Some(250));
+        let expected = reference_np(&expand(&structure));
+        assert!(
+            (stats.normalized_power - expected).abs() < 1e-6,
+            "{} != {expected}",
+            stats.normalized_power
+        );
+        let joules: u32 = expand(&structure).iter().map(|(s, w)| s * w).sum();
+        assert!((stats.kilojoules - joules as f64 / 1000.0).abs() < 1e-9);
+        assert_eq!(stats.time_in_zone.unwrap()[6], 3 * 20 * 10);
+    }
+
+    #[test]
+    fn calculate_without_ftp() {
+        let stats = calculate(&[step(1200, 180, 180)], None);
+        assert_eq!(stats.duration, 1200);
+        assert_eq!(stats.intensity_factor, None);
+        assert_eq!(stats.tss, None);
+        assert_eq!(stats.time_in_zone, None);
+    }
+
+    #[test]
+    fn calculate_shorter_than_the_np_window() {
+        let stats = calculate(&[step(10, 300, 300), step(10, 100, 100)], None);
+        assert!((stats.normalized_power - stats.avg_power).abs() < 1e-9);
+        assert_eq!(calculate(&[], Some(200)), WorkoutStats::default());
+        assert_eq!(
+            calculate(&[repeat(0, vec![step(60, 200, 200)])], Some(200)),
+            WorkoutStats::default()
+        );
+    }
+
+    #[test]
+    fn calculate_does_not_overflow() {
+        assert_eq!(target_watts(&step(60, u32::MAX, u32::MAX)), u32::MAX);
+        let structure = [step(u32::MAX, 200, 200), step(u32::MAX, 200, 200)];
+        let stats = calculate(&structure, Some(200));
+        assert_eq!(stats.duration, u32::MAX);
+        assert_eq!(stats.time_in_zone.unwrap()[3], u32::MAX);
+        // repeated without expanding every repetition
+        let structure = [repeat(
+            u32::MAX,
+            vec![repeat(u32::MAX, vec![step(60, 200, 200)])],
+        )];
+        let stats = calculate(&structure, Some(200));
+        assert_eq!(stats.duration, u32::MAX);
+        assert_eq!(stats.time_in_zone.unwrap()[3], u32::MAX);
+        assert!((stats.avg_power - 200.0).abs() < 1e-6);
+        // the repeated rolling averages lose some precision
+        assert!((stats.normalized_power - 200.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,519 @@
+use crate::util::FitSource;
+use crate::{OutputFormat, UploadArgs};
+use futures::stream::{self, StreamExt};
+use reqwest::StatusCode;
+use serde::Serialize;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+use workout::api::WorkoutData;
+use workout::stats::{self, WorkoutStats};
+use workout::{api, Adjustment, PowerScale};
+
+/// The first retry waits this long, every following retry doubles it up to `RETRY_MAX_DELAY`.
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub(crate) struct UploadReport {
+    pub(crate) profile: Option<String>,
+    /// The file path, or the entry name inside a zip package
+    pub(crate) source: String,
+    pub(crate) attempts: u32,
+    /// `None` when the FIT file couldn't be converted
+    pub(crate) stats: Option<WorkoutStats>,
+    pub(crate) result: Result<UploadResponse, String>,
+}
+
+#[derive(Debug)]
+pub(crate) struct UploadResponse {
+    /// `None` for `--dry-run`, nothing was sent
+    pub(crate) status: Option<StatusCode>,
+    /// The id iGPS gave the new workout
+    pub(crate) workout_id: Option<u64>,
+}
+
+impl UploadResponse {
+    fn describe(&self) -> String {
+        match (self.status, self.workout_id) {
+            (None, _) => "dry run".to_string(),
+            (Some(status), Some(id)) => format!("{}, workout id: {}", status, id),
+            (Some(status), None) => status.to_string(),
+        }
+    }
+}
+
+/// Where and as whom the workouts are uploaded
+#[derive(Debug)]
+pub(crate) struct Account {
+    /// The `--profile` name, `None` for the default account
+    pub(crate) profile: Option<String>,
+    pub(crate) api_url: String,
+    pub(crate) token: String,
+    pub(crate) power_scale: PowerScale,
+}
+
+/// One line of `--output json`
+#[derive(Serialize, Debug)]
+struct UploadRecord<'a> {
+    profile: Option<&'a str>,
+    source: String,
+    status: &'a str,
+    attempts: u32,
+    igps_id: Option<u64>,
+    stats: Option<&'a WorkoutStats>,
+    error: Option<&'a str>,
+}
+
+impl UploadReport {
+    fn print(&self, output: OutputFormat) {
+        match output {
+            OutputFormat::Text => {
+                let profile = match &self.profile {
+                    Some(p) => format!("profile: {p}, "),
+                    None => String::new(),
+                };
+                match (&self.result, &self.stats) {
+                    (Ok(UploadResponse { status: None, .. }), Some(stats)) => {
+                        println!("{profile}path: {:?}, {}", self.source, stats.describe());
+                        if let Some(zones) = stats.describe_zones() {
+                            println!("    {zones}");
+                        }
+                    }
+                    (Ok(res), _) => println!(
+                        "{profile}path: {:?}, upload success: {}",
+                        self.source,
+                        res.describe()
+                    ),
+                    (Err(e), _) => {
+                        eprintln!("{profile}path: {:?}, upload fail: {e}", self.source)
+                    }
+                }
+            }
+            OutputFormat::Json => {
+                let record = UploadRecord {
+                    profile: self.profile.as_deref(),
+                    source: self.source.clone(),
+                    status: match &self.result {
+                        Ok(UploadResponse { status: None, .. }) => "dry_run",
+                        Ok(_) => "ok",
+                        Err(_) => "failed",
+                    },
+                    attempts: self.attempts,
+                    igps_id: self.result.as_ref().ok().and_then(|r| r.workout_id),
+                    stats: self.stats.as_ref(),
+                    error: self.result.as_ref().err().map(|e| e.as_str()),
+                };
+                match serde_json::to_string(&record) {
+                    Ok(line) => println!("{line}"),
+                    Err(e) => eprintln!("path: {:?}, serialize record fail: {e}", self.source),
+                }
+            }
+        }
+    }
+}
+
+/// Spaces requests out so that no more than `rate` requests are sent per second.
+struct RateLimiter {
+    interval: Option<Duration>,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(rate: f64) -> Self {
+        let interval = if rate > 0.0 {
+            Some(Duration::from_secs_f64(1.0 / rate))
+        } else {
+            None
+        };
+        Self {
+            interval,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    async fn wait(&self) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+        let at = {
+            let mut next = self.next.lock().await;
+            let at = (*next).max(Instant::now());
+            *next = at + interval;
+            at
+        };
+        tokio::time::sleep_until(at).await;
+    }
+}
+
+pub(crate) async fn upload_all(
+    sources: &[FitSource],
+    account: &Account,
+    adjustment: &Option<Adjustment>,
+    step_name: &str,
+    upload_args: &UploadArgs,
+    output: OutputFormat,
+) -> Vec<UploadReport> {
+    let limiter = RateLimiter::new(upload_args.rate);
+    let mut reports: Vec<UploadReport> = stream::iter(sources)
+        .map(|fit_source| {
+            upload_fit(
+                fit_source,
+                account,
+                adjustment,
+                step_name,
+                &limiter,
+                upload_args,
+            )
+        })
+        .buffer_unordered(upload_args.concurrency.max(1))
+        .inspect(|report| report.print(output))
+        .collect()
+        .await;
+    reports.sort_by(|a, b| a.source.cmp(&b.source));
+    reports
+}
+
+async fn upload_fit(
+    fit_source: &FitSource,
+    account: &Account,
+    adjustment: &Option<Adjustment>,
+    step_name: &str,
+    limiter: &RateLimiter,
+    upload_args: &UploadArgs,
+) -> UploadReport {
+    let mut report = UploadReport {
+        profile: account.profile.clone(),
+        source: fit_source.name(),
+        attempts: 0,
+        stats: None,
+        result: Err(String::new()),
+    };
+    let (workout_data, stats) = match prepare_workout(
+        fit_source,
+        account,
+        adjustment,
+        step_name,
+        upload_args.stats_description,
+    ) {
+        Ok(w) => w,
+        Err(e) => {
+            report.result = Err(e);
+            return report;
+        }
+    };
+    report.stats = Some(stats);
+    if upload_args.dry_run {
+        report.result = Ok(UploadResponse {
+            status: None,
+            workout_id: None,
+        });
+        return report;
+    }
+    let workout_json = match serde_json::to_string(&api::IGPSRequestBody { data: workout_data }) {
+        Ok(json) => json,
+        Err(e) => {
+            report.result = Err(format!("serialize workout fail: {e}"));
+            return report;
+        }
+    };
+
+    loop {
+        report.attempts += 1;
+        limiter.wait().await;
+        let err = match api::push_to_igps(
+            &account.api_url,
+            workout_json.clone(),
+            account.token.clone(),
+        )
+        .await
+        {
+            Ok(res) => {
+                let status = res.status();
+                let body = res.text().await.unwrap_or_default();
+                if is_transient_status(status) {
+                    format!("response status: {status}, body: {body}")
+                } else {
+                    report.result = api::parse_edit_workout_response(status, &body)
+                        .map(|workout_id| UploadResponse {
+                            status: Some(status),
+                            workout_id,
+                        })
+                        .map_err(|e| e.to_string());
+                    return report;
+                }
+            }
+            // the connection failed, nothing was sent
+            Err(api::IGPSError::Http(e)) if e.is_connect() => e.to_string(),
+            // iGPS may have saved the workout before the timeout, a retry could save it twice
+            Err(api::IGPSError::Http(e)) if e.is_timeout() => {
+                report.result = Err(format!(
+                    "{e}, iGPS may have saved the workout, check before uploading it again"
+                ));
+                return report;
+            }
+            Err(e) => {
+                report.result = Err(e.to_string());
+                return report;
+            }
+        };
+        if report.attempts > upload_args.retries {
+            report.result = Err(err);
+            return report;
+        }
+        let delay = retry_delay(report.attempts);
+        eprintln!(
+            "path: {:?}, attempt {} fail: {err}, retry in {delay:?}",
+            report.source, report.attempts
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Convert the FIT (or iGPS JSON) file for `account` and calculate its statistics with the athlete's FTP.
+fn prepare_workout(
+    fit_source: &FitSource,
+    account: &Account,
+    adjustment: &Option<Adjustment>,
+    step_name: &str,
+    stats_description: bool,
+) -> Result<(WorkoutData, WorkoutStats), String> {
+    let fit_file = fit_source
+        .read()
+        .map_err(|e| format!("read file fail: {e}"))?;
+    let mut workout_data =
+        workout::load_workout(fit_file, adjustment, account.power_scale, step_name)
+            .map_err(|e| e.to_string())?;
+    workout_data
+        .validate()
+        .map_err(|e| format!("invalid workout: {e}"))?;
+    for warning in workout_data.warnings() {
+        eprintln!("path: {:?}, warning: {warning}", fit_source.name());
+    }
+    let stats = stats::calculate(&workout_data.structure, account.power_scale.athlete_ftp());
+    if stats_description {
+        if !workout_data.description.is_empty() {
+            workout_data.description.push('\n');
+        }
+        workout_data.description.push_str(&stats.describe());
+    }
+    Ok((workout_data, stats))
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// How long to wait after the `attempts`th failed attempt
+fn retry_delay(attempts: u32) -> Duration {
+    RETRY_BASE_DELAY
+        .saturating_mul(2_u32.saturating_pow(attempts.saturating_sub(1)))
+        .min(RETRY_MAX_DELAY)
+}
+
+/// Print a table of all uploads, returns `true` when every upload succeeded.
+pub(crate) fn print_summary(reports: &[UploadReport]) -> bool {
+    let failed = reports.iter().filter(|r| r.result.is_err()).count();
+    println!();
+    println!(
+        "Summary: {} succeeded, {} failed",
+        reports.len() - failed,
+        failed
+    );
+    let profile_width = reports
+        .iter()
+        .filter_map(|r| r.profile.as_ref().map(|p| p.chars().count()))
+        .max()
+        .map(|w| w.max("PROFILE".len()));
+    let width = reports
+        .iter()
+        .map(|r| r.source.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max("FILE".len());
+    let profile_column = |profile: &str| match profile_width {
+        Some(w) => format!("{:<w$} ", profile),
+        None => String::new(),
+    };
+    println!(
+        "{}{:<7} {:<width$} {:>8}  DETAIL",
+        profile_column("PROFILE"),
+        "STATUS",
+        "FILE",
+        "ATTEMPTS"
+    );
+    for report in reports {
+        let (status, detail) = match (&report.result, &report.stats) {
+            (Ok(UploadResponse { status: None, .. }), Some(stats)) => ("ok", stats.describe()),
+            (Ok(res), _) => ("ok", res.describe()),
+            (Err(e), _) => ("failed", e.clone()),
+        };
+        println!(
+            "{}{:<7} {:<width$} {:>8}  {}",
+            profile_column(report.profile.as_deref().unwrap_or("")),
+            status,
+            report.source,
+            report.attempts,
+            detail
+        );
+    }
+    failed == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn only_server_errors_and_too_many_requests_are_retried() {
+        assert!(is_transient_status(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(is_transient_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(is_transient_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!is_transient_status(StatusCode::OK));
+        assert!(!is_transient_status(StatusCode::BAD_REQUEST));
+        assert!(!is_transient_status(StatusCode::UNAUTHORIZED));
+    }
+
+    #[test]
+    fn the_retry_delay_doubles_up_to_the_maximum() {
+        assert_eq!(retry_delay(1), Duration::from_millis(500));
+        assert_eq!(retry_delay(2), Duration::from_secs(1));
+        assert_eq!(retry_delay(3), Duration::from_secs(2));
+        assert_eq!(retry_delay(8), RETRY_MAX_DELAY);
+        assert_eq!(retry_delay(u32::MAX), RETRY_MAX_DELAY);
+    }
+
+    #[tokio::test]
+    async fn the_rate_limiter_spaces_the_requests() {
+        let limiter = RateLimiter::new(20.0);
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.wait().await;
+        }
+        // the first request goes out at once, the next two 50ms apart
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn a_rate_of_zero_is_unlimited() {
+        let limiter = RateLimiter::new(0.0);
+        let start = Instant::now();
+        for _ in 0..100 {
+            limiter.wait().await;
+        }
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    /// Answer each request with the next status of `statuses`, returns the API URL and the
+    /// number of requests served.
+    async fn serve(statuses: &'static [u16]) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let served = Arc::new(AtomicUsize::new(0));
+        let counter = served.clone();
+        tokio::spawn(async move {
+            for status in statuses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                // read the headers and the whole body before answering
+                loop {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request);
+                    if let Some(end) = text.find("\r\n\r\n") {
+                        let length = text[..end]
+                            .lines()
+                            .find_map(|l| {
+                                l.to_ascii_lowercase()
+                                    .strip_prefix("content-length: ")
+                                    .map(str::to_string)
+                            })
+                            .and_then(|l| l.trim().parse::<usize>().ok())
+                            .unwrap_or(0);
+                        if request.len() >= end + 4 + length {
+                            break;
+                        }
+                    }
+                    if n == 0 {
+                        break;
+                    }
+                }
+                counter.fetch_add(1, Ordering::SeqCst);
+                let body = if *status == 200 {
+                    r#"{"success":true,"data":42}"#
+                } else {
+                    r#"{"success":false,"message":"nope"}"#
+                };
+                let response = format!(
+                    "HTTP/1.1 {status} X\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, served)
+    }
+
+    async fn upload(api_url: String) -> UploadReport {
+        let source = FitSource::File(
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples/W2_5_125.fit"),
+        );
+        let account = Account {
+            profile: None,
+            api_url,
+            token: "token".to_string(),
+            power_scale: PowerScale {
+                source_ftp: Some(200),
+                ftp: None,
+            },
+        };
+        let upload_args = UploadArgs {
+            concurrency: 1,
+            rate: 0.0,
+            retries: 2,
+            dry_run: false,
+            stats_description: false,
+        };
+        upload_fit(
+            &source,
+            &account,
+            &None,
+            workout::DEFAULT_STEP_NAME,
+            &RateLimiter::new(0.0),
+            &upload_args,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn server_errors_and_too_many_requests_are_retried() {
+        let (url, served) = serve(&[503, 429, 200]).await;
+        let report = upload(url).await;
+        assert_eq!(served.load(Ordering::SeqCst), 3);
+        assert_eq!(report.attempts, 3);
+        assert_eq!(report.result.unwrap().workout_id, Some(42));
+    }
+
+    #[tokio::test]
+    async fn a_client_error_isnt_retried() {
+        let (url, served) = serve(&[400, 200]).await;
+        let report = upload(url).await;
+        assert_eq!(served.load(Ordering::SeqCst), 1);
+        assert_eq!(report.attempts, 1);
+        assert!(report.result.is_err());
+    }
+
+    #[tokio::test]
+    async fn the_last_retry_failing_is_an_error() {
+        let (url, served) = serve(&[503, 503, 503]).await;
+        let report = upload(url).await;
+        assert_eq!(served.load(Ordering::SeqCst), 3);
+        assert!(report.result.unwrap_err().contains("503"));
+    }
+}
@@ -1,32 +1,258 @@
+use std::error::Error;
+use std::fs;
 use std::fs::File;
-use std::io::copy;
-use std::path::Path;
+use std::io::{Cursor, Read, Seek};
+use std::path::{Path, PathBuf};
+use workout::api::utils::is_json;
 use zip::read::ZipArchive;
 
-pub fn unzip_file(zip_path: &str, extract_to: &str) -> Result<(), Box<dyn std::error::Error>> {
+/// Zips inside zips are opened up to this depth, deeper ones are skipped.
+const MAX_ZIP_DEPTH: usize = 4;
+
+/// Entries that unpack to more than this are skipped, FIT workouts are a few KB.
+const MAX_ENTRY_SIZE: u64 = 64 * 1024 * 1024;
+
+/// A package unpacks to at most this much, nested zips included, the rest is skipped.
+const MAX_TOTAL_SIZE: u64 = 256 * 1024 * 1024;
+
+/// A FIT file waiting to be uploaded, either on disk or already read into memory.
+#[derive(Debug)]
+pub(crate) enum FitSource {
+    File(PathBuf),
+    Memory { name: String, data: Vec<u8> },
+}
+
+impl FitSource {
+    pub(crate) fn name(&self) -> String {
+        match self {
+            FitSource::File(path) => path.to_string_lossy().to_string(),
+            FitSource::Memory { name, .. } => name.clone(),
+        }
+    }
+
+    pub(crate) fn read(&self) -> std::io::Result<Vec<u8>> {
+        match self {
+            FitSource::File(path) => fs::read(path),
+            FitSource::Memory { data, .. } => Ok(data.clone()),
+        }
+    }
+}
+
+pub(crate) fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension()
+        .and_then(|s| s.to_str())
+        .is_some_and(|s| s.eq_ignore_ascii_case(extension))
+}
+
+pub(crate) fn is_zip(data: &[u8]) -> bool {
+    data.starts_with(b"PK\x03\x04")
+}
+
+/// The 12 or 14 byte FIT header has `.FIT` at offset 8
+fn is_fit(data: &[u8]) -> bool {
+    matches!(data.first(), Some(12 | 14)) && data.get(8..12) == Some(b".FIT")
+}
+
+/// Read every FIT entry of a zip package into memory, nested zips included.
+pub(crate) fn read_zip_file(zip_path: &str) -> Result<Vec<FitSource>, Box<dyn Error>> {
     let file = File::open(zip_path)?;
-    let mut archive = ZipArchive::new(file)?;
+    let mut sources = Vec::new();
+    let mut budget = MAX_TOTAL_SIZE;
+    read_zip(file, zip_path, 0, &mut budget, &mut sources)?;
+    sources.sort_by_key(|s| s.name());
+    Ok(sources)
+}
+
+/// Turn downloaded or piped bytes into sources, they may hold a single FIT file, an iGPS JSON
+/// workout or a zip package.
+pub(crate) fn read_fit_bytes(name: &str, data: Vec<u8>) -> Result<Vec<FitSource>, Box<dyn Error>> {
+    if !is_zip(&data) {
+        if !is_fit(&data) && !is_json(&data) {
+            return Err(format!("{name:?} it's not a fit file!").into());
+        }
+        return Ok(vec![FitSource::Memory {
+            name: name.to_string(),
+            data,
+        }]);
+    }
+    let mut sources = Vec::new();
+    let mut budget = MAX_TOTAL_SIZE;
+    read_zip(Cursor::new(data), name, 0, &mut budget, &mut sources)?;
+    sources.sort_by_key(|s| s.name());
+    Ok(sources)
+}
+
+/// `budget` is how many bytes the package may still unpack to, a corrupt or oversized entry is
+/// reported and skipped.
+fn read_zip<R: Read + Seek>(
+    reader: R,
+    zip_name: &str,
+    depth: usize,
+    budget: &mut u64,
+    sources: &mut Vec<FitSource>,
+) -> Result<(), Box<dyn Error>> {
+    let mut archive = ZipArchive::new(reader)?;
 
     for i in 0..archive.len() {
-        let mut file = archive.by_index(i)?;
-        let outpath = match file.enclosed_name() {
-            Some(path) => Path::new(extract_to).join(path),
+        let mut file = match archive.by_index(i) {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!(
+                    "entry {i} of {:?} can't be read, skip it! msg: {e}",
+                    zip_name
+                );
+                continue;
+            }
+        };
+        if file.is_dir() {
+            continue;
+        }
+        let path = match file.enclosed_name() {
+            Some(path) => path.to_path_buf(),
             None => continue,
         };
+        let name = format!("{}/{}", zip_name, path.to_string_lossy());
 
-        if (*file.name()).ends_with('/') {
-            std::fs::create_dir_all(&outpath)?;
-        } else {
-            if let Some(p) = outpath.parent() {
-                if !p.exists() {
-                    std::fs::create_dir_all(&p)?;
+        if has_extension(&path, "fit") {
+            match read_entry(&mut file, budget) {
+                Ok(data) => sources.push(FitSource::Memory { name, data }),
+                Err(e) => eprintln!("{:?} can't be read, skip it! msg: {e}", name),
+            }
+        } else if has_extension(&path, "zip") {
+            if depth + 1 >= MAX_ZIP_DEPTH {
+                eprintln!("{:?} is nested too deep, skip it!", name);
+                continue;
+            }
+            let data = match read_entry(&mut file, budget) {
+                Ok(data) => data,
+                Err(e) => {
+                    eprintln!("{:?} can't be read, skip it! msg: {e}", name);
+                    continue;
                 }
+            };
+            if let Err(e) = read_zip(Cursor::new(data), &name, depth + 1, budget, sources) {
+                eprintln!("{:?} can't be read, skip it! msg: {e}", name);
             }
-
-            let mut outfile = File::create(&outpath)?;
-            copy(&mut file, &mut outfile)?;
+        } else {
+            eprintln!("{:?} it's not a fit file!", name);
         }
     }
 
     Ok(())
 }
+
+/// Unpack one entry, the size in the zip header isn't trusted.
+fn read_entry(file: &mut impl Read, budget: &mut u64) -> Result<Vec<u8>, String> {
+    let limit = MAX_ENTRY_SIZE.min(*budget);
+    let mut data = Vec::new();
+    file.take(limit + 1)
+        .read_to_end(&mut data)
+        .map_err(|e| e.to_string())?;
+    let size = data.len() as u64;
+    if size > MAX_ENTRY_SIZE {
+        return Err(format!("it unpacks to more than {MAX_ENTRY_SIZE} bytes"));
+    }
+    if size > *budget {
+        return Err(format!(
+            "the package unpacks to more than {MAX_TOTAL_SIZE} bytes"
+        ));
+    }
+    *budget -= size;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::{FileOptions, ZipWriter};
+
+    /// A zip package of `(name, content)` entries
+    fn zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in entries {
+            writer.start_file(*name, FileOptions::default()).unwrap();
+            writer.write_all(content).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    const FIT_HEADER: &[u8] = b"\x0e\x10\x00\x00\x00\x00\x00\x00.FIT\x00\x00";
+
+    fn names(sources: &[FitSource]) -> Vec<String> {
+        sources.iter().map(FitSource::name).collect()
+    }
+
+    #[test]
+    fn a_single_fit_file_is_read_as_is() {
+        let sources = read_fit_bytes("-", FIT_HEADER.to_vec()).unwrap();
+        assert_eq!(names(&sources), vec!["-"]);
+        assert_eq!(sources[0].read().unwrap(), FIT_HEADER);
+        let sources = read_fit_bytes("-", b" {\"title\":\"a\"}".to_vec()).unwrap();
+        assert_eq!(names(&sources), vec!["-"]);
+    }
+
+    #[test]
+    fn other_bytes_are_an_error() {
+        let result = read_fit_bytes("https://example.com/a.fit", b"<html></html>".to_vec());
+        assert!(result.is_err());
+        assert!(read_fit_bytes("-", Vec::new()).is_err());
+    }
+
+    #[test]
+    fn nested_zips_are_opened() {
+        let inner = zip(&[("b.fit", b"b")]);
+        let package = zip(&[("a.fit", b"a"), ("week 2/inner.zip", &inner)]);
+        let sources = read_fit_bytes("plan.zip", package).unwrap();
+        assert_eq!(
+            names(&sources),
+            vec!["plan.zip/a.fit", "plan.zip/week 2/inner.zip/b.fit"]
+        );
+        assert_eq!(sources[1].read().unwrap(), b"b");
+    }
+
+    #[test]
+    fn zips_nested_too_deep_are_skipped() {
+        let mut package = zip(&[("deep.fit", b"deep")]);
+        for _ in 0..MAX_ZIP_DEPTH {
+            package = zip(&[("a.fit", b"a"), ("next.zip", &package)]);
+        }
+        let sources = read_fit_bytes("plan.zip", package).unwrap();
+        assert_eq!(sources.len(), MAX_ZIP_DEPTH);
+        assert!(names(&sources).iter().all(|name| name.ends_with("/a.fit")));
+    }
+
+    #[test]
+    fn entries_over_the_budget_are_skipped() {
+        let package = zip(&[
+            ("a.fit", &[0; 600]),
+            ("b.fit", &[0; 500]),
+            ("c.fit", &[0; 300]),
+        ]);
+        let mut budget = 1_000;
+        let mut sources = Vec::new();
+        read_zip(
+            Cursor::new(package),
+            "plan.zip",
+            0,
+            &mut budget,
+            &mut sources,
+        )
+        .unwrap();
+        // b.fit doesn't fit into what a.fit left over, c.fit does
+        assert_eq!(names(&sources), vec!["plan.zip/a.fit", "plan.zip/c.fit"]);
+        assert_eq!(budget, 100);
+    }
+
+    #[test]
+    fn other_entries_are_skipped() {
+        let package = zip(&[("readme.txt", b"hello"), ("a.FIT", b"a"), ("dir/", b"")]);
+        let sources = read_fit_bytes("plan.zip", package).unwrap();
+        assert_eq!(names(&sources), vec!["plan.zip/a.FIT"]);
+    }
+
+    #[test]
+    fn a_broken_zip_is_an_error() {
+        assert!(read_fit_bytes("plan.zip", b"PK\x03\x04broken".to_vec()).is_err());
+    }
+}
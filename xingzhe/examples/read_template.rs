@@ -9,12 +9,11 @@ fn main() {
     for data in &fit.data {
         match data {
             FitMessage::Definition(_) => {}
-            FitMessage::Data(msg) => match msg.data.message_type {
-                MessageType::Session => {
+            FitMessage::Data(msg) => {
+                if msg.data.message_type == MessageType::Session {
                     println!("Data: {:?}", msg.data);
                 }
-                _ => {}
-            },
+            }
         }
     }
 }
@@ -0,0 +1,401 @@
+use crate::geo::{haversine, interpolate};
+use crate::lap::{self, AutoLap, Lap, LapTrigger};
+use crate::summary::{Summary, MAX_SAMPLE_GAP};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+/// The sport of every converted workout, as the FIT profile names it
+pub(crate) const SPORT: &str = "cycling";
+
+/// Indoor rides have no polyline, sensors the ride didn't have are `null`
+#[derive(Serialize, Deserialize)]
+struct XingZhePoint {
+    #[serde(default)]
+    points: Vec<Point>,
+    encoding_points: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Point {
+    heartrate: Option<f32>,
+    power: Option<f32>,
+    time: f64,
+    altitude: Option<f32>,
+    speed: Option<f32>,
+    cadence: Option<f32>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Segment {
+    workout: WorkoutSession,
+}
+
+/// The totals XingZhe calculated for a workout, what the ride didn't record is `null` or missing
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct WorkoutSession {
+    pub(crate) title: String,
+    #[serde(default)]
+    pub(crate) elevation_gain: Option<u16>,
+    #[serde(default)]
+    pub(crate) elevation_loss: Option<u16>,
+    pub(crate) start_time: u64,
+    pub(crate) end_time: u64,
+    pub(crate) distance: u32,
+    pub(crate) down_distance: u32,
+    pub(crate) up_distance: u32,
+    pub(crate) calories: u32,
+    pub(crate) max_speed: f32,
+    #[serde(default)]
+    pub(crate) max_altitude: Option<u16>,
+    #[serde(default)]
+    pub(crate) max_grade: Option<i16>,
+    #[serde(default)]
+    pub(crate) min_grade: Option<i16>,
+    pub(crate) avg_speed: f32,
+    #[serde(default)]
+    pub(crate) avg_heartrate: Option<u8>,
+    #[serde(default)]
+    pub(crate) max_heartrate: Option<u8>,
+    #[serde(default)]
+    pub(crate) avg_cadence: Option<u8>,
+    #[serde(default)]
+    pub(crate) max_cadence: Option<u8>,
+    #[serde(rename = "powerMax", default)]
+    pub(crate) power_max: Option<u16>,
+    #[serde(rename = "powerAvg", default)]
+    pub(crate) power_avg: Option<u16>,
+    #[serde(rename = "powerTSS")]
+    pub(crate) power_tss: f32,
+    #[serde(rename = "powerIF")]
+    pub(crate) power_if: f32,
+    #[serde(rename = "powerNP")]
+    pub(crate) power_np: f32,
+}
+
+/// One recorded workout, whatever file format it's written in
+#[derive(Debug)]
+pub(crate) struct Activity {
+    pub(crate) title: String,
+    pub(crate) sport: &'static str,
+    /// Unix time in seconds
+    pub(crate) start_time: u32,
+    pub(crate) records: Vec<Record>,
+    /// Only a fallback for what the records don't have
+    pub(crate) session: WorkoutSession,
+    /// FTP of the athlete, for IF and TSS
+    pub(crate) ftp: Option<u32>,
+    /// One lap for the whole ride without it
+    pub(crate) auto_lap: Option<AutoLap>,
+}
+
+/// One sample of the ride
+#[derive(Clone, Debug)]
+pub(crate) struct Record {
+    /// Unix time in seconds
+    pub(crate) timestamp: u32,
+    /// Latitude and longitude in degrees, `None` for rides without GPS
+    pub(crate) position: Option<(f64, f64)>,
+    /// Meters, `None` without barometer or GPS altitude
+    pub(crate) altitude: Option<f32>,
+    /// Meters since the start
+    pub(crate) distance: f64,
+    /// Meters per second
+    pub(crate) speed: f32,
+    /// `None` without heart rate monitor
+    pub(crate) heart_rate: Option<u8>,
+    /// `None` without cadence sensor
+    pub(crate) cadence: Option<u8>,
+    /// Watts, `None` without power meter
+    pub(crate) power: Option<u16>,
+}
+
+impl Activity {
+    /// Build the activity from the `segment` and `points` JSON of one workout
+    pub(crate) fn parse(
+        segment: &str,
+        points: &str,
+        ftp: Option<u32>,
+        auto_lap: Option<AutoLap>,
+    ) -> Result<Activity, Box<dyn Error>> {
+        let segment: Segment = serde_json::from_str(segment)?;
+        let session = segment.workout;
+        let xingzhe: XingZhePoint = serde_json::from_str(points)?;
+        let mut coordinates: Vec<(f64, f64)> = match xingzhe.encoding_points.as_deref() {
+            None | Some("") => vec![],
+            Some(encoded) => polyline::decode_polyline(encoded, 5)
+                .map_err(|e| format!("invalid polyline: {e}"))?
+                .0
+                .iter()
+                .map(|c| (c.y, c.x))
+                .collect(),
+        };
+        // the distance, the pauses and the laps need the samples in order, a coordinate per
+        // sample moves with its sample
+        let mut order: Vec<usize> = (0..xingzhe.points.len()).collect();
+        order.sort_by(|&a, &b| xingzhe.points[a].time.total_cmp(&xingzhe.points[b].time));
+        let points: Vec<&Point> = order.iter().map(|&i| &xingzhe.points[i]).collect();
+        if coordinates.len() == points.len() {
+            coordinates = order.iter().map(|&i| coordinates[i]).collect();
+        }
+
+        let mut distance = 0.0;
+        let mut last_time: Option<f64> = None;
+        let mut records: Vec<Record> = points
+            .iter()
+            .map(|point| {
+                let speed = point.speed.unwrap_or(0.0);
+                // the speed of a sample holds since the one before, unless there's a pause between
+                if let Some(last_time) = last_time {
+                    let secs = ((point.time - last_time) / 1000.0).max(0.0);
+                    if secs <= MAX_SAMPLE_GAP as f64 {
+                        distance += speed as f64 * secs;
+                    }
+                }
+                last_time = Some(point.time);
+                Record {
+                    timestamp: (point.time / 1000.0) as u32,
+                    position: None,
+                    altitude: point.altitude,
+                    distance,
+                    speed,
+                    // 0 is no valid heart rate, a monitor that lost contact
+                    heart_rate: point.heartrate.map(|hr| hr as u8).filter(|hr| *hr > 0),
+                    cadence: point.cadence.map(|cadence| cadence as u8),
+                    power: point.power.map(|power| power as u16),
+                }
+            })
+            .collect();
+        let positions = positions(&coordinates, &records);
+        for (record, position) in records.iter_mut().zip(positions) {
+            record.position = position;
+        }
+        if records.iter().all(|r| r.speed <= 0.0) {
+            distance_from_gps(&mut records);
+        }
+
+        Ok(Activity {
+            title: session.title.clone(),
+            sport: SPORT,
+            start_time: (session.start_time / 1000) as u32,
+            records,
+            session,
+            ftp,
+            auto_lap,
+        })
+    }
+
+    /// Totals of the whole ride, from the records as far as they go
+    pub(crate) fn summary(&self) -> Summary {
+        Summary::from_records(&self.records, self.ftp).or_session(&self.session)
+    }
+
+    /// The laps of the ride, at least one
+    pub(crate) fn laps(&self) -> Vec<Lap> {
+        let laps = match self.auto_lap {
+            Some(auto_lap) => lap::split(&self.records, auto_lap, self.ftp),
+            None => vec![],
+        };
+        if laps.len() > 1 {
+            return laps;
+        }
+        vec![Lap {
+            records: 0..self.records.len(),
+            summary: self.summary(),
+            trigger: LapTrigger::SessionEnd,
+        }]
+    }
+}
+
+/// Distance and speed from the positions, for rides that recorded no speed. Like the speed, a
+/// pause between two samples doesn't count.
+fn distance_from_gps(records: &mut [Record]) {
+    let mut distance = 0.0;
+    for i in 1..records.len() {
+        let secs = records[i]
+            .timestamp
+            .saturating_sub(records[i - 1].timestamp);
+        if let (Some(from), Some(to)) = (records[i - 1].position, records[i].position) {
+            if secs > 0 && secs <= MAX_SAMPLE_GAP {
+                let meters = haversine(from, to);
+                distance += meters;
+                records[i].speed = (meters / secs as f64) as f32;
+            }
+        }
+        records[i].distance = distance;
+    }
+}
+
+/// The position of every record. The polyline usually has a coordinate for every sample, if it
+/// has more or less they are spread over the ride by the distance of the records (or evenly
+/// without speed), with fewer than two there's no GPS.
+fn positions(coordinates: &[(f64, f64)], records: &[Record]) -> Vec<Option<(f64, f64)>> {
+    if coordinates.len() == records.len() {
+        return coordinates.iter().copied().map(Some).collect();
+    }
+    if coordinates.len() < 2 || records.len() < 2 {
+        return vec![None; records.len()];
+    }
+    // meters along the polyline of every coordinate
+    let mut along = vec![0.0];
+    for pair in coordinates.windows(2) {
+        along.push(along[along.len() - 1] + haversine(pair[0], pair[1]));
+    }
+    let length = along[along.len() - 1];
+    let total = records[records.len() - 1].distance;
+    let mut j = 0;
+    records
+        .iter()
+        .enumerate()
+        .map(|(i, record)| {
+            let fraction = if total > 0.0 {
+                record.distance / total
+            } else {
+                i as f64 / (records.len() - 1) as f64
+            };
+            let target = fraction * length;
+            while j + 2 < coordinates.len() && along[j + 1] < target {
+                j += 1;
+            }
+            let span = along[j + 1] - along[j];
+            let t = if span > 0.0 {
+                ((target - along[j]) / span).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            Some(interpolate(coordinates[j], coordinates[j + 1], t))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{record, SEGMENT_JSON};
+
+    fn records(distances: &[f64]) -> Vec<Record> {
+        distances
+            .iter()
+            .enumerate()
+            .map(|(i, &distance)| Record {
+                distance,
+                ..record(i as u32, 0.0, 0, 0)
+            })
+            .collect()
+    }
+
+    fn assert_close(position: Option<(f64, f64)>, expected: (f64, f64)) {
+        let (lat, long) = position.unwrap();
+        assert!(
+            (lat - expected.0).abs() < 1e-9 && (long - expected.1).abs() < 1e-9,
+            "{:?} != {:?}",
+            (lat, long),
+            expected
+        );
+    }
+
+    #[test]
+    fn positions_one_coordinate_per_sample() {
+        let coordinates = [(30.0, 120.0), (30.001, 120.0), (30.002, 120.001)];
+        let positions = positions(&coordinates, &records(&[0.0, 5.0, 90.0]));
+        assert_eq!(positions, coordinates.map(Some).to_vec());
+    }
+
+    #[test]
+    fn positions_spread_by_distance() {
+        let coordinates = [(30.0, 120.0), (30.01, 120.0)];
+        let positions = positions(&coordinates, &records(&[0.0, 250.0, 1000.0]));
+        assert_close(positions[0], (30.0, 120.0));
+        assert_close(positions[1], (30.0025, 120.0));
+        assert_close(positions[2], (30.01, 120.0));
+    }
+
+    #[test]
+    fn positions_spread_by_index_without_distance() {
+        let coordinates = [
+            (30.0, 120.0),
+            (30.0, 120.01),
+            (30.0, 120.02),
+            (30.0, 120.03),
+        ];
+        let positions = positions(&coordinates, &records(&[0.0; 3]));
+        assert_close(positions[0], (30.0, 120.0));
+        assert_close(positions[1], (30.0, 120.015));
+        assert_close(positions[2], (30.0, 120.03));
+    }
+
+    #[test]
+    fn positions_without_polyline() {
+        assert_eq!(positions(&[], &records(&[0.0, 10.0])), vec![None, None]);
+        assert_eq!(
+            positions(&[(30.0, 120.0)], &records(&[0.0, 10.0])),
+            vec![None, None]
+        );
+    }
+
+    #[test]
+    fn samples_are_sorted_with_their_coordinates() {
+        let points = r#"{"points": [
+            {"heartrate": 142, "power": 210, "time": 1684900802000, "altitude": 12.0, "speed": 5.0, "cadence": 86},
+            {"heartrate": 140, "power": 200, "time": 1684900800000, "altitude": 10.0, "speed": 5.0, "cadence": 85},
+            {"heartrate": 141, "power": 205, "time": 1684900801000, "altitude": 11.0, "speed": 5.0, "cadence": 85}],
+            "encoding_points": "glbvDsol{Uf@RS?"}"#;
+        let activity = Activity::parse(SEGMENT_JSON, points, None, None).unwrap();
+        let samples: Vec<_> = activity
+            .records
+            .iter()
+            .map(|r| (r.timestamp, r.power, r.position))
+            .collect();
+        assert_eq!(
+            samples,
+            vec![
+                (1_684_900_800, Some(200), Some((30.0, 120.0))),
+                (1_684_900_801, Some(205), Some((30.0001, 120.0))),
+                (1_684_900_802, Some(210), Some((30.0002, 120.0001))),
+            ]
+        );
+        let distances: Vec<_> = activity.records.iter().map(|r| r.distance).collect();
+        assert_eq!(distances, vec![0.0, 5.0, 10.0]);
+    }
+
+    #[test]
+    fn sensors_the_ride_did_not_have_are_none() {
+        let points = r#"{"points": [
+            {"heartrate": null, "power": null, "time": 1684900800000, "altitude": null, "speed": 5.0, "cadence": null},
+            {"heartrate": 0, "power": 0, "time": 1684900801000, "altitude": 0.0, "speed": 5.0, "cadence": 0}]}"#;
+        let activity = Activity::parse(SEGMENT_JSON, points, None, None).unwrap();
+        let first = &activity.records[0];
+        assert_eq!(
+            (first.altitude, first.heart_rate, first.cadence, first.power),
+            (None, None, None, None)
+        );
+        // coasting at sea level, but a heart rate of 0 is a monitor without contact
+        let second = &activity.records[1];
+        assert_eq!(
+            (
+                second.altitude,
+                second.heart_rate,
+                second.cadence,
+                second.power
+            ),
+            (Some(0.0), None, Some(0), Some(0))
+        );
+    }
+
+    #[test]
+    fn the_totals_of_sensors_xingzhe_did_not_have_are_none() {
+        let segment = r#"{"workout": {"title": "Indoor", "elevation_gain": null,
+            "start_time": 1684900800000, "end_time": 1684900803000, "distance": 0,
+            "down_distance": 0, "up_distance": 0, "calories": 0, "max_speed": 0.0,
+            "avg_speed": 0.0, "avg_heartrate": null, "powerTSS": 0.0, "powerIF": 0.0,
+            "powerNP": 0.0}}"#;
+        let activity = Activity::parse(segment, r#"{"points": []}"#, None, None).unwrap();
+        assert_eq!(activity.session.elevation_gain, None);
+        assert_eq!(activity.session.avg_heartrate, None);
+        assert_eq!(activity.session.max_altitude, None);
+        let summary = activity.summary();
+        assert_eq!(summary.elapsed_time, 3);
+        assert_eq!(summary.avg_heart_rate, None);
+        assert_eq!(summary.max_altitude, None);
+        assert_eq!(summary.ascent, 0.0);
+    }
+}
@@ -1,5 +1,15 @@
-use reqwest::header::{HeaderMap, COOKIE};
+use reqwest::header::{HeaderMap, HeaderValue, COOKIE};
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::time::Duration;
+
+/// The first retry waits this long, every following retry doubles it up to `RETRY_MAX_DELAY`.
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// A request XingZhe doesn't answer within this long fails with a timeout
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[derive(Serialize, Deserialize, Debug)]
 pub(crate) struct GetMonthList {
@@ -18,52 +28,111 @@ pub(crate) struct GetMonthInfoItem {
     pub(crate) title: String,
 }
 
+pub(crate) const BASE_URL: &str = "https://www.imxingzhe.com";
+
 /// https://www.imxingzhe.com/api/v4/segment_workout/?workout_id=
-pub(crate) async fn segment(workout_id: u128, cookie: &str) -> Result<String, reqwest::Error> {
+pub(crate) async fn segment(
+    base_url: &str,
+    workout_id: u128,
+    cookie: &HeaderValue,
+) -> Result<String, reqwest::Error> {
     let url = format!(
-        "https://www.imxingzhe.com/api/v4/segment_workout/?workout_id={}",
-        workout_id
+        "{}/api/v4/segment_workout/?workout_id={}",
+        base_url, workout_id
     );
     let mut headers = HeaderMap::new();
-    headers.insert(COOKIE, cookie.parse().unwrap());
-    let client = reqwest::Client::builder().build().unwrap();
-    client.get(url).headers(headers).send().await?.text().await
+    headers.insert(COOKIE, cookie.clone());
+    let client = client()?;
+    client
+        .get(url)
+        .headers(headers)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await
 }
 
 /// https://www.imxingzhe.com/api/v1/pgworkout/{}/points/
-pub(crate) async fn points(workout_id: u128, cookie: &str) -> Result<String, reqwest::Error> {
-    let url = format!(
-        "https://www.imxingzhe.com/api/v1/pgworkout/{}/points/",
-        workout_id
-    );
+pub(crate) async fn points(
+    base_url: &str,
+    workout_id: u128,
+    cookie: &HeaderValue,
+) -> Result<String, reqwest::Error> {
+    let url = format!("{}/api/v1/pgworkout/{}/points/", base_url, workout_id);
     let mut headers = HeaderMap::new();
-    headers.insert(COOKIE, cookie.parse().unwrap());
-    let client = reqwest::Client::builder().build().unwrap();
-    client.get(url).headers(headers).send().await?.text().await
+    headers.insert(COOKIE, cookie.clone());
+    let client = client()?;
+    client
+        .get(url)
+        .headers(headers)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await
 }
 
 /// https://www.imxingzhe.com/api/v4/user_month_info/?user_id={}&year={}&month={}
 pub(crate) async fn get_month_list(
+    base_url: &str,
     user_id: u64,
     year: u32,
     month: u32,
-    cookie: &str,
-) -> Vec<GetMonthInfoItem> {
+    cookie: &HeaderValue,
+) -> Result<Vec<GetMonthInfoItem>, reqwest::Error> {
     let url = format!(
-        "https://www.imxingzhe.com/api/v4/user_month_info/?user_id={}&year={}&month={}",
-        user_id, year, month
+        "{}/api/v4/user_month_info/?user_id={}&year={}&month={}",
+        base_url, user_id, year, month
     );
     let mut headers = HeaderMap::new();
-    headers.insert(COOKIE, cookie.parse().unwrap());
-    let client = reqwest::Client::builder().build().unwrap();
+    headers.insert(COOKIE, cookie.clone());
+    let client = client()?;
     let res = client
         .get(url)
         .headers(headers)
         .send()
-        .await
-        .unwrap()
+        .await?
+        .error_for_status()?
         .json::<GetMonthList>()
-        .await
-        .unwrap();
-    res.data.wo_info
+        .await?;
+    Ok(res.data.wo_info)
+}
+
+fn client() -> Result<reqwest::Client, reqwest::Error> {
+    reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build()
+}
+
+/// Run `request` again after a timeout, a connection error, a 5xx or a 429 response, at most
+/// `retries` times, `what` names the request in the retry messages
+pub(crate) async fn with_retries<T, F, Fut>(
+    retries: u32,
+    what: &str,
+    request: F,
+) -> Result<T, reqwest::Error>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T, reqwest::Error>>,
+{
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match request().await {
+            Err(e) if attempts <= retries && is_transient(&e) => {
+                let delay = RETRY_BASE_DELAY
+                    .saturating_mul(2_u32.saturating_pow(attempts - 1))
+                    .min(RETRY_MAX_DELAY);
+                eprintln!("{what}, attempt {attempts} fail: {e}, retry in {delay:?}");
+                tokio::time::sleep(delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn is_transient(e: &reqwest::Error) -> bool {
+    e.is_timeout()
+        || e.is_connect()
+        || e.status()
+            .is_some_and(|s| s.is_server_error() || s == StatusCode::TOO_MANY_REQUESTS)
 }
@@ -1,3 +1,6 @@
+use crate::activity::Activity;
+use crate::lap::{Lap, LapTrigger};
+use crate::summary::{self, Summary};
 use fit_rust::protocol::data_field::DataField;
 use fit_rust::protocol::message_type::MessageType;
 use fit_rust::protocol::value::Value;
@@ -6,93 +9,32 @@ use fit_rust::protocol::{
     FitHeader, FitMessage, FitMessageHeader,
 };
 use fit_rust::Fit;
-use serde::{Deserialize, Serialize};
-
-#[derive(Serialize, Deserialize)]
-struct XingZhePoint {
-    points: Vec<Point>,
-    encoding_points: String,
-}
-
-#[derive(Serialize, Deserialize)]
-struct Point {
-    heartrate: f32,
-    power: f32,
-    time: f64,
-    altitude: f32,
-    speed: f32,
-    cadence: f32,
-}
-
-#[derive(Serialize, Deserialize)]
-struct Segment {
-    workout: WorkoutSession,
-}
-
-#[derive(Serialize, Deserialize)]
-struct WorkoutSession {
-    title: String,
-    elevation_gain: u16,
-    elevation_loss: u16,
-    start_time: u64,
-    end_time: u64,
-    distance: u32,
-    down_distance: u32,
-    up_distance: u32,
-    calories: u32,
-    max_speed: f32,
-    max_altitude: u16,
-    max_grade: i16,
-    min_grade: i16,
-    avg_speed: f32,
-    avg_heartrate: u8,
-    max_heartrate: u8,
-    avg_cadence: u8,
-    max_cadence: u8,
-    #[serde(rename = "powerMax")]
-    power_max: u16,
-    #[serde(rename = "powerAvg")]
-    power_avg: u16,
-    #[serde(rename = "powerTSS")]
-    power_tss: f32,
-    #[serde(rename = "powerIF")]
-    power_if: f32,
-    #[serde(rename = "powerNP")]
-    power_np: f32,
-}
+use std::error::Error;
+use std::path::Path;
 
 #[derive(Clone, Debug)]
 struct FitRecord {
-    /// lat
-    pub lat: f32,
-    /// long
-    pub long: f32,
-    /// alt
-    pub alt: u16,
-    /// heart
+    /// lat and long in degrees, written as semicircles, records without position use their own
+    /// definition
+    pub position: Option<(f32, f32)>,
+    /// enhanced_alt, scale 5, offset 500, the invalid value without altitude
+    pub alt: u32,
+    /// heart, the invalid value without heart rate monitor
     pub heart: u8,
-    /// cadence
+    /// cadence, the invalid value without cadence sensor
     pub cadence: u8,
-    /// distance
+    /// distance, scale 100
     pub distance: u32,
-    /// speed
-    pub speed: u16,
-    /// power
+    /// enhanced_speed, scale 1000
+    pub speed: u32,
+    /// power, the invalid value without power meter
     pub power: u16,
-    /// temperature
-    pub temperature: i8,
     /// timestamp
     pub timestamp: u32,
 }
 
-pub fn generate_fit(segment: String, points: String) {
-    let segment: Segment = serde_json::from_str(&segment).unwrap();
-    let session = segment.workout;
-    let xinzhe: XingZhePoint = serde_json::from_str(&points).unwrap();
-    let decoded_polyline = polyline::decode_polyline(&xinzhe.encoding_points, 5)
-        .unwrap()
-        .0;
-
+/// Write `activity` as a FIT file to `path`
+pub fn write_fit(activity: &Activity, path: &Path) -> Result<(), Box<dyn Error>> {
     let mut write_fit: Fit = Fit {
         header: FitHeader {
             header_size: 14,
@@ -105,8 +47,10 @@ pub fn generate_fit(segment: String, points: String) {
         data: vec![],
     };
     let mut fit_data: Vec<FitMessage> = vec![];
-    let start_time = (&session.start_time / 1000) as u32;
-    let end_time = (&session.end_time / 1000) as u32;
+    let summary = activity.summary();
+    let laps = activity.laps();
+    let start_time = summary.start_time;
+    let end_time = summary.end_time;
     fit_data.push(build_igps_file_id_def());
     fit_data.push(build_igps_file_id(start_time));
     fit_data.push(build_file_creator_def());
@@ -119,36 +63,45 @@ pub fn generate_fit(segment: String, points: String) {
         DataField::new(0, Value::Enum("timer")),
         DataField::new(1, Value::Enum("start")),
     ]));
-    fit_data.push(build_record_def());
-
-    // record
-    let mut current_distance = 0_u32;
-    let mut min_alt = u16::MAX;
-    let mut total_alt = 0_u64;
-    for (i, v) in xinzhe.points.iter().enumerate() {
-        let coordinate = decoded_polyline[i];
-        let alt = (v.altitude * 5.0 + 500.0) as u16;
-        total_alt += alt as u64;
-        if alt < min_alt {
-            min_alt = alt;
+    fit_data.push(build_record_def(true));
+    if activity.records.iter().any(|r| r.position.is_none()) {
+        fit_data.push(build_record_def(false));
+    }
+
+    // record, the timer stops after the sample a pause starts with and starts again before the
+    // one it ends with, a pause until the end of the ride leaves it stopped
+    let mut pauses = summary::pauses(&activity.records).into_iter().peekable();
+    // samples can share a timestamp, the timer stops once per pause
+    let mut stopped = false;
+    for v in &activity.records {
+        if let Some(pause) = pauses.next_if(|p| p.end == v.timestamp && p.end < end_time) {
+            stopped = false;
+            fit_data.push(build_event(vec![
+                DataField::new(253, Value::Time(pause.end)),
+                DataField::new(0, Value::Enum("timer")),
+                DataField::new(1, Value::Enum("start")),
+            ]));
         }
-        let speed = (v.speed * 1000.0) as u16;
-        current_distance += speed as u32 * 100;
         let record = FitRecord {
-            lat: coordinate.y as f32,
-            long: coordinate.x as f32,
-            alt: alt,
-            heart: v.heartrate as u8,
-            cadence: v.cadence as u8,
-            distance: current_distance,
-            speed,
-            power: v.power as u16,
-            temperature: 25,
-            timestamp: (v.time / 1000.0) as u32,
+            position: v.position.map(|(lat, long)| (lat as f32, long as f32)),
+            alt: v.altitude.map(fit_enhanced_altitude).unwrap_or(u32::MAX),
+            heart: v.heart_rate.unwrap_or(u8::MAX),
+            cadence: v.cadence.unwrap_or(u8::MAX),
+            distance: (v.distance * 100.0).round() as u32,
+            speed: (v.speed * 1000.0).round() as u32,
+            power: v.power.unwrap_or(u16::MAX),
+            timestamp: v.timestamp,
         };
         fit_data.push(build_record(record));
+        if !stopped && pauses.peek().is_some_and(|p| p.start == v.timestamp) {
+            stopped = true;
+            fit_data.push(build_event(vec![
+                DataField::new(253, Value::Time(v.timestamp)),
+                DataField::new(0, Value::Enum("timer")),
+                DataField::new(1, Value::Enum("stop_all")),
+            ]));
+        }
     }
-    let avg_alt = (total_alt / xinzhe.points.len() as u64) as u16;
 
     fit_data.push(build_event(vec![
         DataField::new(253, Value::Time(end_time)),
@@ -168,14 +121,23 @@ pub fn generate_fit(segment: String, points: String) {
         DataField::new(1, Value::Enum("stop_disable_all")),
     ]));
 
+    fit_data.push(build_lap_def());
+    for (index, lap) in laps.iter().enumerate() {
+        fit_data.push(build_lap(get_lap_vec(activity, index as u16, lap)));
+    }
+
     // build session
     fit_data.push(build_session_def());
-    fit_data.push(build_session(get_session_vec(&session, min_alt, avg_alt)));
+    fit_data.push(build_session(get_session_vec(
+        activity,
+        &summary,
+        laps.len() as u16,
+    )));
 
     fit_data.push(build_activity_def());
     fit_data.push(build_activity(vec![
         DataField::new(253, Value::Time(end_time)),
-        DataField::new(0, Value::U32(end_time - start_time)),
+        DataField::new(0, Value::U32(summary.timer_time.saturating_mul(1000))),
         DataField::new(5, Value::Time(start_time)),
         DataField::new(1, Value::U16(0)),
         DataField::new(2, Value::Enum("manual")),
@@ -184,87 +146,248 @@ pub fn generate_fit(segment: String, points: String) {
     ]));
 
     fit_data.push(build_sport_def());
-    fit_data.push(build_sport());
+    fit_data.push(build_sport(activity.sport));
 
     write_fit.data = fit_data;
-    write_fit
-        .write(format!("xingzhe/data/{}.fit", session.title))
-        .unwrap();
+    write_fit.write(path)?;
+    Ok(())
 }
 
-fn get_session_vec(session: &WorkoutSession, min_alt: u16, avg_alt: u16) -> Vec<DataField> {
-    let start_time = (&session.start_time / 1000) as u32;
-    let end_time = (&session.end_time / 1000) as u32;
-    let max_alt = session.max_altitude * 5 + 500;
+/// Scale 5, offset 500, as the FIT profile stores altitudes
+fn fit_altitude(meters: f32) -> u16 {
+    ((meters + 500.0) * 5.0).clamp(0.0, u16::MAX as f32 - 1.0) as u16
+}
+
+/// Like `fit_altitude` for the 32 bit `enhanced_altitude`
+fn fit_enhanced_altitude(meters: f32) -> u32 {
+    ((meters as f64 + 500.0) * 5.0)
+        .round()
+        .clamp(0.0, u32::MAX as f64 - 1.0) as u32
+}
+
+/// Scale 1000, as the FIT profile stores speeds in m/s
+fn fit_speed(meters_per_second: f32) -> u16 {
+    (meters_per_second * 1000.0)
+        .round()
+        .clamp(0.0, u16::MAX as f32 - 1.0) as u16
+}
+
+/// The 16 bit field next to an enhanced one, invalid when the value doesn't fit
+fn u16_or_enhanced(v: u32) -> Value {
+    Value::U16(u16::try_from(v).unwrap_or(u16::MAX))
+}
+
+// the invalid value of the field when the ride didn't record it
+fn u8_or_invalid(v: Option<u8>) -> Value {
+    Value::U8(v.unwrap_or(u8::MAX))
+}
+
+fn u16_or_invalid(v: Option<u16>) -> Value {
+    Value::U16(v.unwrap_or(u16::MAX))
+}
+
+fn altitude(v: Option<f32>) -> Value {
+    Value::U16(v.map(fit_altitude).unwrap_or(u16::MAX))
+}
+
+/// Percent, scale 100, the invalid value when XingZhe has none
+fn grade(v: Option<i16>) -> Value {
+    Value::I16(
+        v.map(|grade| grade.saturating_mul(100).min(i16::MAX - 1))
+            .unwrap_or(i16::MAX),
+    )
+}
+
+fn get_lap_vec(activity: &Activity, index: u16, lap: &Lap) -> Vec<DataField> {
+    let summary = &lap.summary;
     vec![
         // end time
-        DataField::new(253, Value::Time(end_time)),
+        DataField::new(253, Value::Time(summary.end_time)),
         // start time
-        DataField::new(2, Value::Time(start_time)),
+        DataField::new(2, Value::Time(summary.start_time)),
         // total_elapsed_time
-        DataField::new(7, Value::U32((end_time - start_time) * 1000)),
+        DataField::new(7, Value::U32(summary.elapsed_time.saturating_mul(1000))),
         // total_timer_time
-        DataField::new(8, Value::U32((end_time - start_time) * 1000)),
+        DataField::new(8, Value::U32(summary.timer_time.saturating_mul(1000))),
         // total_distance
-        DataField::new(9, Value::U32(session.distance * 100)),
+        DataField::new(9, Value::U32((summary.distance * 100.0) as u32)),
         // total_moving_time
-        DataField::new(59, Value::U32((end_time - start_time) * 1000)),
+        DataField::new(52, Value::U32(summary.moving_time.saturating_mul(1000))),
         // message_index field
-        DataField::new(254, Value::U16(1)),
+        DataField::new(254, Value::U16(index)),
         // total_calories
-        DataField::new(11, Value::U16((session.calories / 1000) as u16)),
+        DataField::new(11, Value::U16(summary.calories as u16)),
+        // avg_speed
+        DataField::new(13, Value::U16(fit_speed(summary.avg_speed))),
+        // max_speed
+        DataField::new(14, Value::U16(fit_speed(summary.max_speed))),
+        // avg power
+        DataField::new(19, u16_or_invalid(summary.avg_power)),
+        // max power
+        DataField::new(20, u16_or_invalid(summary.max_power)),
+        // total_ascent
+        DataField::new(21, Value::U16(summary.ascent.round() as u16)),
+        // total_descent
+        DataField::new(22, Value::U16(summary.descent.round() as u16)),
+        // normalized_power
+        DataField::new(
+            33,
+            u16_or_invalid(summary.normalized_power.map(|np| np.round() as u16)),
+        ),
+        // avg_altitude
+        DataField::new(42, altitude(summary.avg_altitude)),
+        // max_altitude
+        DataField::new(43, altitude(summary.max_altitude)),
+        // min_altitude
+        DataField::new(62, altitude(summary.min_altitude)),
+        DataField::new(0, Value::Enum("lap")),
+        DataField::new(1, Value::Enum("stop")),
+        DataField::new(
+            24,
+            Value::Enum(match lap.trigger {
+                LapTrigger::Distance => "distance",
+                LapTrigger::Time => "time",
+                LapTrigger::SessionEnd => "session_end",
+            }),
+        ),
+        DataField::new(25, Value::Enum(activity.sport)),
+        // avg_heart_rate
+        DataField::new(15, u8_or_invalid(summary.avg_heart_rate)),
+        // max_heart_rate
+        DataField::new(16, u8_or_invalid(summary.max_heart_rate)),
+        // avg_cadence
+        DataField::new(17, u8_or_invalid(summary.avg_cadence)),
+        // max_cadence
+        DataField::new(18, u8_or_invalid(summary.max_cadence)),
+        // min_heart_rate
+        DataField::new(63, u8_or_invalid(summary.min_heart_rate)),
+    ]
+}
+
+fn build_lap_def() -> FitMessage {
+    build_fit_def(
+        2,
+        vec![
+            FieldDefinition::new(253, 4, true, 6),
+            FieldDefinition::new(2, 4, true, 6),
+            FieldDefinition::new(7, 4, true, 6),
+            FieldDefinition::new(8, 4, true, 6),
+            FieldDefinition::new(9, 4, true, 6),
+            FieldDefinition::new(52, 4, true, 6),
+            FieldDefinition::new(254, 2, true, 4),
+            FieldDefinition::new(11, 2, true, 4),
+            FieldDefinition::new(13, 2, true, 4),
+            FieldDefinition::new(14, 2, true, 4),
+            FieldDefinition::new(19, 2, true, 4),
+            FieldDefinition::new(20, 2, true, 4),
+            FieldDefinition::new(21, 2, true, 4),
+            FieldDefinition::new(22, 2, true, 4),
+            FieldDefinition::new(33, 2, true, 4),
+            FieldDefinition::new(42, 2, true, 4),
+            FieldDefinition::new(43, 2, true, 4),
+            FieldDefinition::new(62, 2, true, 4),
+            FieldDefinition::new(0, 1, true, 0),
+            FieldDefinition::new(1, 1, true, 0),
+            FieldDefinition::new(24, 1, true, 0),
+            FieldDefinition::new(25, 1, true, 0),
+            FieldDefinition::new(15, 1, true, 2),
+            FieldDefinition::new(16, 1, true, 2),
+            FieldDefinition::new(17, 1, true, 2),
+            FieldDefinition::new(18, 1, true, 2),
+            FieldDefinition::new(63, 1, true, 2),
+        ],
+        MessageType::Lap,
+    )
+}
+
+fn build_lap(values: Vec<DataField>) -> FitMessage {
+    build_fit_message(2, MessageType::Lap, values)
+}
+
+fn get_session_vec(activity: &Activity, summary: &Summary, num_laps: u16) -> Vec<DataField> {
+    let session = &activity.session;
+    vec![
+        // end time
+        DataField::new(253, Value::Time(summary.end_time)),
+        // start time
+        DataField::new(2, Value::Time(summary.start_time)),
+        // total_elapsed_time
+        DataField::new(7, Value::U32(summary.elapsed_time.saturating_mul(1000))),
+        // total_timer_time
+        DataField::new(8, Value::U32(summary.timer_time.saturating_mul(1000))),
+        // total_distance
+        DataField::new(9, Value::U32((summary.distance * 100.0) as u32)),
+        // total_moving_time
+        DataField::new(59, Value::U32(summary.moving_time.saturating_mul(1000))),
+        // message_index field
+        DataField::new(254, Value::U16(0)),
+        // total_calories
+        DataField::new(11, Value::U16(summary.calories as u16)),
         // avg_speed field
-        DataField::new(14, Value::U16((session.avg_speed / 3.6 * 1000.0) as u16)),
+        DataField::new(14, Value::U16(fit_speed(summary.avg_speed))),
         // max_speed
-        DataField::new(15, Value::U16((session.max_speed / 3.6 * 1000.0) as u16)),
+        DataField::new(15, Value::U16(fit_speed(summary.max_speed))),
         // avg power
-        DataField::new(20, Value::U16(session.power_avg)),
+        DataField::new(20, u16_or_invalid(summary.avg_power)),
         // max power
-        DataField::new(21, Value::U16(session.power_max)),
+        DataField::new(21, u16_or_invalid(summary.max_power)),
         // total_ascent
-        DataField::new(22, Value::U16(session.elevation_gain)),
+        DataField::new(22, Value::U16(summary.ascent.round() as u16)),
         // total_descent
-        DataField::new(23, Value::U16(session.elevation_loss)),
+        DataField::new(23, Value::U16(summary.descent.round() as u16)),
         // first_lap_index field
         DataField::new(25, Value::U16(0)),
         // num_laps field
-        DataField::new(26, Value::U16(1)),
+        DataField::new(26, Value::U16(num_laps)),
         // normalized_power field
-        DataField::new(34, Value::U16(session.power_np as u16)),
-        // training_stress_score field
-        DataField::new(35, Value::U16(session.power_tss as u16)),
-        // intensity_factor field
-        DataField::new(36, Value::U16((session.power_if * 1000.0) as u16)),
+        DataField::new(
+            34,
+            u16_or_invalid(summary.normalized_power.map(|np| np.round() as u16)),
+        ),
+        // training_stress_score field, scale 10
+        DataField::new(
+            35,
+            u16_or_invalid(
+                summary
+                    .training_stress_score
+                    .map(|tss| (tss * 10.0).round() as u16),
+            ),
+        ),
+        // intensity_factor field, scale 1000
+        DataField::new(
+            36,
+            u16_or_invalid(
+                summary
+                    .intensity_factor
+                    .map(|intensity| (intensity * 1000.0).round() as u16),
+            ),
+        ),
         // // left_right_balance field
         // DataField::new(37, Value::I16(i16::MAX)),
         // avg_altitude
-        DataField::new(49, Value::U16(avg_alt)),
+        DataField::new(49, altitude(summary.avg_altitude)),
         // max_altitude
-        DataField::new(50, Value::U16(max_alt)),
+        DataField::new(50, altitude(summary.max_altitude)),
         // max_pos_grade
-        DataField::new(55, Value::I16(session.max_grade * 100)),
+        DataField::new(55, grade(session.max_grade)),
         // max_neg_grade
-        DataField::new(56, Value::I16(session.min_grade * 100)),
+        DataField::new(56, grade(session.min_grade)),
         // min_altitude
-        DataField::new(71, Value::U16(min_alt)),
+        DataField::new(71, altitude(summary.min_altitude)),
         DataField::new(0, Value::Enum("session")),
         DataField::new(1, Value::Enum("stop")),
-        DataField::new(5, Value::Enum("cycling")),
+        DataField::new(5, Value::Enum(activity.sport)),
         DataField::new(6, Value::Enum("road")),
         // avg_heart_rate
-        DataField::new(16, Value::U8(session.avg_heartrate)),
+        DataField::new(16, u8_or_invalid(summary.avg_heart_rate)),
         // max_heart_rate
-        DataField::new(17, Value::U8(session.max_heartrate)),
+        DataField::new(17, u8_or_invalid(summary.max_heart_rate)),
         // avg_cadence
-        DataField::new(18, Value::U8(session.avg_cadence)),
+        DataField::new(18, u8_or_invalid(summary.avg_cadence)),
         // max_cadence
-        DataField::new(19, Value::U8(session.max_cadence)),
-        // avg_temperature
-        DataField::new(57, Value::I8(25)),
-        // max_temperature
-        DataField::new(58, Value::I8(25)),
+        DataField::new(19, u8_or_invalid(summary.max_cadence)),
         // min_heart_rate
-        DataField::new(64, Value::U8(session.avg_heartrate)),
+        DataField::new(64, u8_or_invalid(summary.min_heart_rate)),
     ]
 }
 
@@ -304,8 +427,6 @@ fn build_session_def() -> FitMessage {
             FieldDefinition::new(17, 1, true, 2),
             FieldDefinition::new(18, 1, true, 2),
             FieldDefinition::new(19, 1, true, 2),
-            FieldDefinition::new(57, 1, true, 1),
-            FieldDefinition::new(58, 1, true, 1),
             FieldDefinition::new(64, 1, true, 2),
         ],
         MessageType::Session,
@@ -347,41 +468,49 @@ fn build_igps_file_id_def() -> FitMessage {
 }
 
 fn build_record(record: FitRecord) -> FitMessage {
-    build_fit_message(
-        5,
-        MessageType::Record,
-        vec![
-            DataField::new(0, Value::F32(record.lat)),
-            DataField::new(1, Value::F32(record.long)),
-            DataField::new(2, Value::U16(record.alt)),
-            DataField::new(3, Value::U8(record.heart)),
-            DataField::new(4, Value::U8(record.cadence)),
-            DataField::new(5, Value::U32(record.distance)),
-            DataField::new(6, Value::U16(record.speed)),
-            DataField::new(7, Value::U16(record.power)),
-            DataField::new(13, Value::I8(record.temperature)),
-            DataField::new(253, Value::Time(record.timestamp)),
-        ],
-    )
+    let mut values = vec![];
+    // fit_rust turns degrees into semicircles, it only writes coordinates given as F32
+    if let Some((lat, long)) = record.position {
+        values.push(DataField::new(0, Value::F32(lat)));
+        values.push(DataField::new(1, Value::F32(long)));
+    }
+    values.extend([
+        DataField::new(2, u16_or_enhanced(record.alt)),
+        DataField::new(3, Value::U8(record.heart)),
+        DataField::new(4, Value::U8(record.cadence)),
+        DataField::new(5, Value::U32(record.distance)),
+        DataField::new(6, u16_or_enhanced(record.speed)),
+        DataField::new(7, Value::U16(record.power)),
+        // enhanced_speed
+        DataField::new(73, Value::U32(record.speed)),
+        // enhanced_altitude
+        DataField::new(78, Value::U32(record.alt)),
+        DataField::new(253, Value::Time(record.timestamp)),
+    ]);
+    let local_num = if record.position.is_some() { 5 } else { 8 };
+    build_fit_message(local_num, MessageType::Record, values)
 }
 
-fn build_record_def() -> FitMessage {
-    build_fit_def(
-        5,
-        vec![
-            FieldDefinition::new(0, 4, false, 8),
-            FieldDefinition::new(1, 4, false, 8),
-            FieldDefinition::new(2, 2, true, 4),
-            FieldDefinition::new(3, 1, true, 2),
-            FieldDefinition::new(4, 1, true, 2),
-            FieldDefinition::new(5, 4, true, 6),
-            FieldDefinition::new(6, 2, true, 4),
-            FieldDefinition::new(7, 2, true, 4),
-            FieldDefinition::new(13, 1, true, 1),
-            FieldDefinition::new(253, 4, true, 6),
-        ],
-        MessageType::Record,
-    )
+/// Records without position (e.g. indoor rides) leave out lat and long
+fn build_record_def(position: bool) -> FitMessage {
+    let mut defs = vec![];
+    if position {
+        // sint32 semicircles
+        defs.push(FieldDefinition::new(0, 4, true, 5));
+        defs.push(FieldDefinition::new(1, 4, true, 5));
+    }
+    defs.extend([
+        FieldDefinition::new(2, 2, true, 4),
+        FieldDefinition::new(3, 1, true, 2),
+        FieldDefinition::new(4, 1, true, 2),
+        FieldDefinition::new(5, 4, true, 6),
+        FieldDefinition::new(6, 2, true, 4),
+        FieldDefinition::new(7, 2, true, 4),
+        FieldDefinition::new(73, 4, true, 6),
+        FieldDefinition::new(78, 4, true, 6),
+        FieldDefinition::new(253, 4, true, 6),
+    ]);
+    build_fit_def(if position { 5 } else { 8 }, defs, MessageType::Record)
 }
 
 fn build_file_creator() -> FitMessage {
@@ -451,7 +580,7 @@ fn build_event(values: Vec<DataField>) -> FitMessage {
 
 fn build_event_def() -> FitMessage {
     build_fit_def(
-        3,
+        0,
         vec![
             FieldDefinition::new(253, 4, true, 6),
             FieldDefinition::new(0, 1, false, 0),
@@ -481,13 +610,13 @@ fn build_activity_def() -> FitMessage {
     )
 }
 
-fn build_sport() -> FitMessage {
+fn build_sport(sport: &'static str) -> FitMessage {
     build_fit_message(
         10,
         MessageType::Sport,
         vec![
             DataField::new(3, Value::String("Road Cycling\0\0\0\0".to_string())),
-            DataField::new(0, Value::Enum("cycling")),
+            DataField::new(0, Value::Enum(sport)),
             DataField::new(1, Value::Enum("road")),
         ],
     )
@@ -529,3 +658,113 @@ fn build_fit_def(local_num: u8, values: Vec<FieldDefinition>, msg_type: MessageT
         data: DefinitionMessage::new(false, values.len() as u8, values, msg_type),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{ride, temp_dir};
+
+    #[test]
+    fn speeds_too_fast_for_the_field_are_clamped() {
+        assert_eq!(fit_speed(5.0), 5000);
+        assert_eq!(fit_speed(65.5344), 65534);
+        assert_eq!(fit_speed(100.0), u16::MAX - 1);
+        assert_eq!(fit_speed(-1.0), 0);
+    }
+
+    #[test]
+    fn a_ride_of_months_does_not_overflow_the_times() {
+        let mut activity = ride();
+        let last = activity.records.last_mut().unwrap();
+        // 5 million seconds are more milliseconds than a u32 holds
+        last.timestamp += 5_000_000;
+        last.speed = 100.0;
+        let path = temp_dir("fit-overflow").join("ride.fit");
+        write_fit(&activity, &path).unwrap();
+        assert!(path.exists());
+    }
+
+    /// The data messages of type `message_type` of the FIT file at `path`, as fit_rust reads them
+    fn read(path: &Path, message_type: MessageType) -> Vec<Vec<DataField>> {
+        let fit = Fit::read(std::fs::read(path).unwrap()).unwrap();
+        fit.data
+            .into_iter()
+            .filter_map(|message| match message {
+                FitMessage::Data(data) if data.data.message_type == message_type => {
+                    Some(data.data.values)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn field(values: &[DataField], field_num: u8) -> &Value {
+        &values
+            .iter()
+            .find(|v| v.field_num == field_num)
+            .unwrap_or_else(|| panic!("no field {field_num}"))
+            .value
+    }
+
+    #[test]
+    fn records_read_back_as_written() {
+        let path = temp_dir("fit-records").join("ride.fit");
+        write_fit(&ride(), &path).unwrap();
+        let records = read(&path, MessageType::Record);
+        assert_eq!(records.len(), 4);
+        let record = &records[1];
+        // fit_rust only turns sint32 semicircles back into degrees
+        let (Value::F32(lat), Value::F32(long)) = (field(record, 0), field(record, 1)) else {
+            panic!("no semicircles: {record:?}");
+        };
+        assert!((lat - 30.0001).abs() < 1e-5 && (long - 120.0).abs() < 1e-5);
+        assert_eq!(field(record, 253), &Value::Time(1_684_900_801));
+        assert_eq!(field(record, 3), &Value::U8(141));
+        assert_eq!(field(record, 4), &Value::U8(85));
+        assert_eq!(field(record, 5), &Value::U32(500));
+        assert_eq!(field(record, 7), &Value::U16(205));
+        // enhanced_speed and enhanced_altitude, next to the 16 bit fields
+        assert_eq!(field(record, 73), &Value::U32(5000));
+        assert_eq!(field(record, 6), &Value::U16(5000));
+        assert_eq!(field(record, 78), &Value::U32(2553));
+        assert_eq!(field(record, 2), &Value::U16(2553));
+    }
+
+    #[test]
+    fn what_a_sample_did_not_measure_is_invalid() {
+        let mut activity = ride();
+        let record = &mut activity.records[1];
+        record.position = None;
+        record.altitude = None;
+        record.heart_rate = None;
+        record.cadence = None;
+        record.power = None;
+        let path = temp_dir("fit-invalid").join("ride.fit");
+        write_fit(&activity, &path).unwrap();
+        let records = read(&path, MessageType::Record);
+        let record = &records[1];
+        assert!(record.iter().all(|v| v.field_num != 0 && v.field_num != 1));
+        assert_eq!(field(record, 3), &Value::U8(u8::MAX));
+        assert_eq!(field(record, 4), &Value::U8(u8::MAX));
+        assert_eq!(field(record, 7), &Value::U16(u16::MAX));
+        assert_eq!(field(record, 78), &Value::U32(u32::MAX));
+        assert_eq!(field(record, 2), &Value::U16(u16::MAX));
+        // the records around it keep theirs
+        assert_eq!(field(&records[2], 3), &Value::U8(142));
+    }
+
+    #[test]
+    fn the_session_reads_back_as_written() {
+        let path = temp_dir("fit-session").join("ride.fit");
+        write_fit(&ride(), &path).unwrap();
+        let sessions = read(&path, MessageType::Session);
+        assert_eq!(sessions.len(), 1);
+        let session = &sessions[0];
+        assert_eq!(field(session, 2), &Value::Time(1_684_900_800));
+        assert_eq!(field(session, 7), &Value::U32(3000));
+        assert_eq!(field(session, 9), &Value::U32(1500));
+        assert_eq!(field(session, 16), &Value::U8(141));
+        assert_eq!(field(session, 17), &Value::U8(142));
+        assert_eq!(read(&path, MessageType::Lap).len(), 1);
+    }
+}
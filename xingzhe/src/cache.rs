@@ -0,0 +1,93 @@
+use std::fs;
+use std::path::PathBuf;
+
+/// Below the output directory when no cache directory is given
+pub(crate) const DEFAULT_CACHE_DIR: &str = "raw";
+
+/// The raw `segment` and `points` responses of every downloaded workout, so it can be converted
+/// again without asking XingZhe
+#[derive(Debug)]
+pub(crate) struct RawCache {
+    dir: PathBuf,
+}
+
+impl RawCache {
+    pub(crate) fn new(dir: PathBuf) -> RawCache {
+        RawCache { dir }
+    }
+
+    fn segment_path(&self, workout_id: u128) -> PathBuf {
+        self.dir.join(format!("{workout_id}-segment.json"))
+    }
+
+    fn points_path(&self, workout_id: u128) -> PathBuf {
+        self.dir.join(format!("{workout_id}-points.json"))
+    }
+
+    pub(crate) fn save(&self, workout_id: u128, segment: &str, points: &str) -> Result<(), String> {
+        fs::create_dir_all(&self.dir).map_err(|e| format!("create {:?} fail: {e}", self.dir))?;
+        for (path, content) in [
+            (self.segment_path(workout_id), segment),
+            (self.points_path(workout_id), points),
+        ] {
+            fs::write(&path, content).map_err(|e| format!("write {:?} fail: {e}", path))?;
+        }
+        Ok(())
+    }
+
+    /// The `segment` and `points` JSON of `workout_id`
+    pub(crate) fn load(&self, workout_id: u128) -> Result<(String, String), String> {
+        let read = |path: PathBuf| {
+            fs::read_to_string(&path).map_err(|e| format!("read {:?} fail: {e}", path))
+        };
+        Ok((
+            read(self.segment_path(workout_id))?,
+            read(self.points_path(workout_id))?,
+        ))
+    }
+
+    /// Every workout with a cached segment, in id order
+    pub(crate) fn workout_ids(&self) -> Result<Vec<u128>, String> {
+        let entries =
+            fs::read_dir(&self.dir).map_err(|e| format!("read {:?} fail: {e}", self.dir))?;
+        let mut ids: Vec<u128> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name();
+                name.to_str()?.strip_suffix("-segment.json")?.parse().ok()
+            })
+            .collect();
+        ids.sort_unstable();
+        Ok(ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{temp_dir, POINTS_JSON, SEGMENT_JSON};
+
+    #[test]
+    fn saved_responses_load_unchanged() {
+        let cache = RawCache::new(temp_dir("cache-save").join(DEFAULT_CACHE_DIR));
+        cache.save(42, SEGMENT_JSON, POINTS_JSON).unwrap();
+        assert_eq!(
+            cache.load(42).unwrap(),
+            (SEGMENT_JSON.to_string(), POINTS_JSON.to_string())
+        );
+        assert!(cache.load(43).is_err());
+    }
+
+    #[test]
+    fn workout_ids_are_the_cached_segments_in_order() {
+        let dir = temp_dir("cache-ids");
+        let cache = RawCache::new(dir.clone());
+        for workout_id in [300, 7, 42] {
+            cache.save(workout_id, SEGMENT_JSON, POINTS_JSON).unwrap();
+        }
+        fs::write(dir.join("notes.json"), "{}").unwrap();
+        fs::write(dir.join("x-segment.json"), "{}").unwrap();
+        assert_eq!(cache.workout_ids().unwrap(), vec![7, 42, 300]);
+        assert!(RawCache::new(dir.join("missing")).workout_ids().is_err());
+    }
+}
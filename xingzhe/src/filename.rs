@@ -0,0 +1,174 @@
+use crate::month::date;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// `{title}.fit`, the name the FIT files always had
+pub(crate) const DEFAULT_TEMPLATE: &str = "{title}";
+
+/// Names longer than this are cut, most file systems allow 255 bytes
+const MAX_NAME_LEN: usize = 200;
+
+/// What a file name template can refer to
+#[derive(Debug)]
+pub(crate) struct NameFields<'a> {
+    pub(crate) workout_id: u128,
+    pub(crate) title: &'a str,
+    pub(crate) sport: &'a str,
+    /// Unix time in seconds
+    pub(crate) start_time: u32,
+}
+
+/// Where the exported files go and how they are named
+#[derive(Debug)]
+pub(crate) struct FileNaming {
+    output_dir: PathBuf,
+    template: String,
+    /// Paths handed out in this run, two workouts with the same name don't overwrite each other
+    taken: HashSet<PathBuf>,
+}
+
+impl FileNaming {
+    pub(crate) fn new(output_dir: PathBuf, template: String) -> FileNaming {
+        FileNaming {
+            output_dir,
+            template,
+            taken: HashSet::new(),
+        }
+    }
+
+    pub(crate) fn output_dir(&self) -> &Path {
+        &self.output_dir
+    }
+
+    /// A free path for `fields` with `extension`.
+    ///
+    /// `{date}`, `{id}`, `{title}` and `{sport}` are filled in, characters file systems don't
+    /// allow are replaced with `_`, and ` (2)`, ` (3)`... is appended when the name is taken.
+    /// `replacing`, the earlier export of the same workout, doesn't count as taken.
+    pub(crate) fn path(
+        &mut self,
+        fields: &NameFields,
+        extension: &str,
+        replacing: Option<&Path>,
+    ) -> PathBuf {
+        let name = sanitize(&render(&self.template, fields));
+        let name = if name.is_empty() {
+            fields.workout_id.to_string()
+        } else {
+            name
+        };
+        let mut path = self.output_dir.join(format!("{name}.{extension}"));
+        let mut n = 1;
+        while Some(path.as_path()) != replacing && (self.taken.contains(&path) || path.exists()) {
+            n += 1;
+            path = self.output_dir.join(format!("{name} ({n}).{extension}"));
+        }
+        self.taken.insert(path.clone());
+        path
+    }
+}
+
+fn render(template: &str, fields: &NameFields) -> String {
+    template
+        .replace("{date}", &format_date(fields.start_time))
+        .replace("{id}", &fields.workout_id.to_string())
+        .replace("{title}", fields.title)
+        .replace("{sport}", fields.sport)
+}
+
+/// Replace path separators, characters Windows doesn't allow and control characters, and drop
+/// the spaces and dots at both ends
+fn sanitize(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let mut name = name.trim_matches(|c: char| c == '.' || c.is_whitespace());
+    if name.len() > MAX_NAME_LEN {
+        let mut end = MAX_NAME_LEN;
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        name = name[..end].trim_end();
+    }
+    name.to_string()
+}
+
+/// `YYYY-MM-DD` of a unix time in seconds
+fn format_date(secs: u32) -> String {
+    let (year, month, day) = date(secs as u64 * 1000);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(title: &str) -> NameFields<'_> {
+        NameFields {
+            workout_id: 42,
+            title,
+            sport: "cycling",
+            // 2023-05-24 12:00 China Standard Time
+            start_time: 1_684_900_800,
+        }
+    }
+
+    #[test]
+    fn sanitize_replaces_what_file_systems_do_not_allow() {
+        assert_eq!(
+            sanitize("Morning ride: hills/loop"),
+            "Morning ride_ hills_loop"
+        );
+        assert_eq!(sanitize(r#"a\b*c?d"e<f>g|h"#), "a_b_c_d_e_f_g_h");
+        assert_eq!(sanitize("tab\there"), "tab_here");
+        assert_eq!(sanitize(" .hidden. "), "hidden");
+        assert_eq!(sanitize("..."), "");
+    }
+
+    #[test]
+    fn sanitize_cuts_long_names_on_a_char_boundary() {
+        let name = sanitize(&"骑".repeat(100));
+        assert!(name.len() <= MAX_NAME_LEN);
+        assert_eq!(name, "骑".repeat(MAX_NAME_LEN / 3));
+    }
+
+    #[test]
+    fn path_fills_in_the_template() {
+        let dir = std::env::temp_dir().join("xingzhe-filename-template");
+        let mut naming = FileNaming::new(dir.clone(), "{date} {title} {id} {sport}".to_string());
+        assert_eq!(
+            naming.path(&fields("Ride"), "fit", None),
+            dir.join("2023-05-24 Ride 42 cycling.fit")
+        );
+        let mut naming = FileNaming::new(dir.clone(), DEFAULT_TEMPLATE.to_string());
+        assert_eq!(naming.path(&fields("/"), "gpx", None), dir.join("_.gpx"));
+        assert_eq!(naming.path(&fields(""), "gpx", None), dir.join("42.gpx"));
+    }
+
+    #[test]
+    fn path_numbers_names_that_are_taken() {
+        let dir = std::env::temp_dir().join("xingzhe-filename-collision");
+        let mut naming = FileNaming::new(dir.clone(), DEFAULT_TEMPLATE.to_string());
+        let first = naming.path(&fields("Ride"), "fit", None);
+        assert_eq!(first, dir.join("Ride.fit"));
+        assert_eq!(
+            naming.path(&fields("Ride"), "fit", None),
+            dir.join("Ride (2).fit")
+        );
+        assert_eq!(
+            naming.path(&fields("Ride"), "fit", None),
+            dir.join("Ride (3).fit")
+        );
+        assert_eq!(
+            naming.path(&fields("Ride"), "gpx", None),
+            dir.join("Ride.gpx")
+        );
+        // the earlier export of the same workout is replaced
+        assert_eq!(naming.path(&fields("Ride"), "fit", Some(&first)), first);
+    }
+}
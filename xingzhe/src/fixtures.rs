@@ -0,0 +1,50 @@
+//! Rides and directories for the tests
+
+use crate::activity::{Activity, Record};
+use std::fs;
+use std::path::PathBuf;
+
+/// The `segment` response of a four second ride
+pub(crate) const SEGMENT_JSON: &str = r#"{"workout": {"title": "Morning ride",
+    "elevation_gain": 2, "elevation_loss": 0, "start_time": 1684900800000,
+    "end_time": 1684900803000, "distance": 15, "down_distance": 0, "up_distance": 15,
+    "calories": 2000, "max_speed": 18.0, "max_altitude": 12, "max_grade": 3, "min_grade": 0,
+    "avg_speed": 18.0, "avg_heartrate": 141, "max_heartrate": 142, "avg_cadence": 85,
+    "max_cadence": 86, "powerMax": 210, "powerAvg": 205, "powerTSS": 0.2, "powerIF": 0.8,
+    "powerNP": 205.0}}"#;
+
+/// The `points` response of the ride of `SEGMENT_JSON`, one coordinate per sample
+pub(crate) const POINTS_JSON: &str = r#"{"points": [
+    {"heartrate": 140, "power": 200, "time": 1684900800000, "altitude": 10.0, "speed": 5.0, "cadence": 85},
+    {"heartrate": 141, "power": 205, "time": 1684900801000, "altitude": 10.5, "speed": 5.0, "cadence": 85},
+    {"heartrate": 142, "power": 210, "time": 1684900802000, "altitude": 11.5, "speed": 5.0, "cadence": 86},
+    {"heartrate": 141, "power": 205, "time": 1684900803000, "altitude": 12.0, "speed": 5.0, "cadence": 85}],
+    "encoding_points": "_kbvD_ol{US?SSS?"}"#;
+
+/// The ride of `SEGMENT_JSON` and `POINTS_JSON`, without FTP and auto-lap
+pub(crate) fn ride() -> Activity {
+    Activity::parse(SEGMENT_JSON, POINTS_JSON, None, None).unwrap()
+}
+
+/// A sample `timestamp` seconds into a ride at a steady `speed`, without position, heart rate
+/// and altitude
+pub(crate) fn record(timestamp: u32, speed: f32, cadence: u8, power: u16) -> Record {
+    Record {
+        timestamp,
+        position: None,
+        altitude: None,
+        distance: timestamp as f64 * speed as f64,
+        speed,
+        heart_rate: None,
+        cadence: Some(cadence),
+        power: Some(power),
+    }
+}
+
+/// An empty directory of its own for the test `name`
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("xingzhe-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,154 @@
+use crate::activity::{Activity, Record};
+use crate::month::format_utc;
+use std::error::Error;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+/// Write `activity` as a GPX 1.1 track to `path`.
+///
+/// Heart rate and cadence go into the Garmin `TrackPointExtension`, power into the Garmin
+/// `PowerExtension`. Records without position are left out, what a record didn't measure is
+/// left out of its trackpoint.
+pub(crate) fn write_gpx(activity: &Activity, path: &Path) -> Result<(), Box<dyn Error>> {
+    if activity.records.is_empty() {
+        return Err(
+            "GPX needs a track, the workout has no samples, use --format fit or tcx".into(),
+        );
+    }
+    if activity.records.iter().all(|r| r.position.is_none()) {
+        return Err("GPX needs coordinates, the workout has none (an indoor ride?), use --format fit or tcx".into());
+    }
+    let mut gpx = String::new();
+    let _ = writeln!(gpx, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(
+        gpx,
+        r#"<gpx version="1.1" creator="igps_tools xingzhe" xmlns="http://www.topografix.com/GPX/1/1" xmlns:gpxtpx="http://www.garmin.com/xmlschemas/TrackPointExtension/v1" xmlns:gpxpx="http://www.garmin.com/xmlschemas/PowerExtension/v1" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:schemaLocation="http://www.topografix.com/GPX/1/1 http://www.topografix.com/GPX/1/1/gpx.xsd">"#
+    );
+    let _ = writeln!(
+        gpx,
+        "  <metadata>\n    <name>{}</name>\n    <time>{}</time>\n  </metadata>",
+        escape(&activity.title),
+        format_utc(activity.start_time)
+    );
+    let _ = writeln!(
+        gpx,
+        "  <trk>\n    <name>{}</name>\n    <type>{}</type>\n    <trkseg>",
+        escape(&activity.title),
+        activity.sport
+    );
+    for record in &activity.records {
+        let Some((lat, lon)) = record.position else {
+            continue;
+        };
+        let _ = writeln!(gpx, r#"      <trkpt lat="{lat:.6}" lon="{lon:.6}">"#);
+        if let Some(altitude) = record.altitude {
+            let _ = writeln!(gpx, "        <ele>{altitude:.1}</ele>");
+        }
+        let _ = writeln!(gpx, "        <time>{}</time>", format_utc(record.timestamp));
+        if record.heart_rate.is_some() || record.cadence.is_some() || record.power.is_some() {
+            write_extensions(&mut gpx, record);
+        }
+        let _ = writeln!(gpx, "      </trkpt>");
+    }
+    let _ = writeln!(gpx, "    </trkseg>\n  </trk>\n</gpx>");
+    fs::write(path, gpx)?;
+    Ok(())
+}
+
+fn write_extensions(gpx: &mut String, record: &Record) {
+    let _ = writeln!(gpx, "        <extensions>");
+    if record.heart_rate.is_some() || record.cadence.is_some() {
+        let _ = writeln!(gpx, "          <gpxtpx:TrackPointExtension>");
+        if let Some(heart_rate) = record.heart_rate {
+            let _ = writeln!(gpx, "            <gpxtpx:hr>{heart_rate}</gpxtpx:hr>");
+        }
+        if let Some(cadence) = record.cadence {
+            let _ = writeln!(gpx, "            <gpxtpx:cad>{cadence}</gpxtpx:cad>");
+        }
+        let _ = writeln!(gpx, "          </gpxtpx:TrackPointExtension>");
+    }
+    if let Some(power) = record.power {
+        let _ = writeln!(
+            gpx,
+            "          <gpxpx:PowerInWatts>{power}</gpxpx:PowerInWatts>"
+        );
+    }
+    let _ = writeln!(gpx, "        </extensions>");
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{ride, temp_dir};
+
+    fn gpx(activity: &Activity, name: &str) -> Result<String, Box<dyn Error>> {
+        let path = temp_dir(name).join("ride.gpx");
+        write_gpx(activity, &path)?;
+        Ok(fs::read_to_string(path)?)
+    }
+
+    #[test]
+    fn every_sample_is_a_trackpoint() {
+        let gpx = gpx(&ride(), "gpx-track").unwrap();
+        assert!(gpx.contains("<name>Morning ride</name>"));
+        assert!(gpx.contains("<time>2023-05-24T04:00:00Z</time>"));
+        assert_eq!(gpx.matches("<trkpt ").count(), 4);
+        assert!(gpx.contains(r#"<trkpt lat="30.000100" lon="120.000000">"#));
+        assert!(gpx.contains("<ele>10.5</ele>"));
+        assert!(gpx.contains("<gpxtpx:hr>142</gpxtpx:hr>"));
+        assert!(gpx.contains("<gpxtpx:cad>86</gpxtpx:cad>"));
+    }
+
+    #[test]
+    fn the_extensions_are_in_declared_namespaces() {
+        let gpx = gpx(&ride(), "gpx-namespaces").unwrap();
+        assert!(gpx
+            .contains(r#"xmlns:gpxtpx="http://www.garmin.com/xmlschemas/TrackPointExtension/v1""#));
+        assert!(gpx.contains(r#"xmlns:gpxpx="http://www.garmin.com/xmlschemas/PowerExtension/v1""#));
+        assert!(gpx.contains("<gpxpx:PowerInWatts>210</gpxpx:PowerInWatts>"));
+        assert!(!gpx.contains("<power>"));
+    }
+
+    #[test]
+    fn titles_are_escaped() {
+        let mut activity = ride();
+        activity.title = "Hills & <loops>".to_string();
+        let gpx = gpx(&activity, "gpx-escape").unwrap();
+        assert!(gpx.contains("<name>Hills &amp; &lt;loops&gt;</name>"));
+    }
+
+    #[test]
+    fn rides_without_positions_are_an_error() {
+        let mut activity = ride();
+        for record in &mut activity.records {
+            record.position = None;
+        }
+        assert!(gpx(&activity, "gpx-indoor").is_err());
+        activity.records.clear();
+        assert!(gpx(&activity, "gpx-empty").is_err());
+    }
+
+    #[test]
+    fn what_a_sample_did_not_measure_is_left_out() {
+        let mut activity = ride();
+        let record = &mut activity.records[1];
+        record.altitude = None;
+        record.heart_rate = None;
+        record.cadence = None;
+        record.power = None;
+        let gpx = gpx(&activity, "gpx-missing").unwrap();
+        let trackpoints: Vec<_> = gpx.split("<trkpt ").skip(1).collect();
+        assert!(!trackpoints[1].contains("<ele>"));
+        assert!(!trackpoints[1].contains("<extensions>"));
+        assert!(trackpoints[2].contains("<ele>11.5</ele>"));
+        assert!(trackpoints[2].contains("<gpxtpx:hr>142</gpxtpx:hr>"));
+    }
+}
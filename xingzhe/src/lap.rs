@@ -0,0 +1,145 @@
+use crate::activity::Record;
+use crate::summary::Summary;
+use std::ops::Range;
+
+/// When auto-lap starts a new lap
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum AutoLap {
+    /// Every so many meters
+    Distance(f64),
+    /// Every so many seconds
+    Time(u32),
+}
+
+/// Why a lap ended
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum LapTrigger {
+    Distance,
+    Time,
+    /// The last lap, or the only one without auto-lap
+    SessionEnd,
+}
+
+/// One lap of the ride
+#[derive(Debug)]
+pub(crate) struct Lap {
+    /// Index of the records of the lap, it starts where the lap before ended
+    pub(crate) records: Range<usize>,
+    pub(crate) summary: Summary,
+    pub(crate) trigger: LapTrigger,
+}
+
+/// Split `records` into laps every `auto_lap`, the last lap is whatever is left.
+///
+/// The times and the distance of every lap count from the last record of the lap before, so the
+/// laps add up to the whole ride, the averages only count the records of the lap.
+pub(crate) fn split(records: &[Record], auto_lap: AutoLap, ftp: Option<u32>) -> Vec<Lap> {
+    let mut laps = vec![];
+    let mut start = 0;
+    for (i, record) in records.iter().enumerate() {
+        // the lap before ends with the record this one starts from
+        let from = &records[start.max(1) - 1];
+        let (done, trigger) = match auto_lap {
+            AutoLap::Distance(meters) => (
+                record.distance - from.distance >= meters,
+                LapTrigger::Distance,
+            ),
+            AutoLap::Time(secs) => (
+                record.timestamp.saturating_sub(from.timestamp) >= secs,
+                LapTrigger::Time,
+            ),
+        };
+        if done && i + 1 < records.len() {
+            laps.push(lap(records, start..i + 1, trigger, ftp));
+            start = i + 1;
+        }
+    }
+    laps.push(lap(
+        records,
+        start..records.len(),
+        LapTrigger::SessionEnd,
+        ftp,
+    ));
+    laps
+}
+
+fn lap(records: &[Record], range: Range<usize>, trigger: LapTrigger, ftp: Option<u32>) -> Lap {
+    Lap {
+        summary: Summary::from_range(records, range.clone(), ftp),
+        records: range,
+        trigger,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::record;
+
+    /// A ride of `secs` seconds at 10 m/s, one sample per second
+    fn ride(secs: u32) -> Vec<Record> {
+        (0..=secs)
+            .map(|t| record(1_000 + t, 10.0, 85, 200))
+            .collect()
+    }
+
+    #[test]
+    fn split_by_distance() {
+        let records = ride(100);
+        let laps = split(&records, AutoLap::Distance(400.0), None);
+        let ranges: Vec<_> = laps.iter().map(|lap| lap.records.clone()).collect();
+        assert_eq!(ranges, vec![0..41, 41..81, 81..101]);
+        let distances: Vec<_> = laps.iter().map(|lap| lap.summary.distance).collect();
+        assert_eq!(distances, vec![400.0, 400.0, 200.0]);
+        let triggers: Vec<_> = laps.iter().map(|lap| lap.trigger).collect();
+        assert_eq!(
+            triggers,
+            vec![
+                LapTrigger::Distance,
+                LapTrigger::Distance,
+                LapTrigger::SessionEnd
+            ]
+        );
+    }
+
+    #[test]
+    fn split_by_time() {
+        let records = ride(150);
+        let laps = split(&records, AutoLap::Time(60), None);
+        let times: Vec<_> = laps.iter().map(|lap| lap.summary.elapsed_time).collect();
+        assert_eq!(times, vec![60, 60, 30]);
+        assert_eq!(
+            laps.iter().map(|lap| lap.summary.elapsed_time).sum::<u32>(),
+            150
+        );
+    }
+
+    #[test]
+    fn a_lap_ending_with_the_ride_is_the_last_one() {
+        let records = ride(120);
+        let laps = split(&records, AutoLap::Time(60), None);
+        assert_eq!(laps.len(), 2);
+        assert_eq!(laps[1].records, 61..121);
+        assert_eq!(laps[1].trigger, LapTrigger::SessionEnd);
+        // shorter than one lap
+        let laps = split(&records, AutoLap::Distance(5_000.0), None);
+        assert_eq!(laps.len(), 1);
+        assert_eq!(laps[0].records, 0..121);
+    }
+
+    #[test]
+    fn the_samples_of_a_lap_count_once() {
+        let mut records = ride(120);
+        for record in &mut records[..61] {
+            record.power = Some(400);
+            record.cadence = Some(100);
+        }
+        let laps = split(&records, AutoLap::Time(60), None);
+        assert_eq!(laps[1].summary.max_power, Some(200));
+        assert_eq!(laps[1].summary.avg_power, Some(200));
+        assert_eq!(laps[1].summary.max_cadence, Some(85));
+        // the last sample of the first lap holds until the second one starts
+        assert_eq!(laps[1].summary.start_time, 1_060);
+        assert_eq!(laps[1].summary.moving_time, 60);
+    }
+}
@@ -1,75 +1,723 @@
-use clap::Parser;
+use activity::Activity;
+use cache::RawCache;
+use clap::{Parser, ValueEnum};
+use config::{Config, XingZheConfig};
+use filename::{FileNaming, NameFields};
+use lap::AutoLap;
+use manifest::Manifest;
+use month::YearMonth;
+use reqwest::header::HeaderValue;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
+mod activity;
 mod api;
 mod build_fit;
+mod cache;
+mod filename;
+#[cfg(test)]
+mod fixtures;
+mod geo;
+mod gpx;
+mod lap;
+mod manifest;
+mod month;
+mod summary;
+mod tcx;
 
 #[derive(Parser, Debug)]
 #[command(version, author, about, long_about = None)]
 pub(crate) struct Cli {
     /// XingZhe user cookie
-    #[arg(short, long)]
-    pub(crate) cookie: String,
+    #[arg(short, long, env = "XINGZHE_COOKIE", hide_env_values = true)]
+    pub(crate) cookie: Option<String>,
 
     /// XingZhe user id
     #[arg(short, long)]
-    pub(crate) user_id: u64,
+    pub(crate) user_id: Option<u64>,
 
-    /// choose convert year
-    #[arg(short, long)]
+    /// choose convert year, every month of it without `--month`
+    #[arg(short, long, conflicts_with_all = ["from", "to", "all"])]
     pub(crate) year: Option<u32>,
 
     /// choose convert month
-    #[arg(short, long)]
+    #[arg(short, long, requires = "year")]
     pub(crate) month: Option<u32>,
 
+    /// First month to convert, `YYYY-MM`
+    #[arg(long, conflicts_with = "all")]
+    pub(crate) from: Option<YearMonth>,
+
+    /// Last month to convert, `YYYY-MM`, defaults to the current month
+    #[arg(long)]
+    pub(crate) to: Option<YearMonth>,
+
+    /// Convert every month since XingZhe started
+    #[arg(long)]
+    pub(crate) all: bool,
+
     /// choose workout id
-    #[arg(short, long)]
+    #[arg(short, long, conflicts_with_all = ["year", "from", "to", "all"])]
     pub(crate) workout_id: Option<u128>,
+
+    /// Where the FIT files are written, created when missing
+    #[arg(short, long)]
+    pub(crate) output_dir: Option<PathBuf>,
+
+    /// FTP of the athlete, for IF and TSS, XingZhe's values are kept without it
+    #[arg(long)]
+    pub(crate) ftp: Option<u32>,
+
+    /// Start a new lap every so many kilometers, one lap for the whole ride by default
+    #[arg(long, value_name = "KM", conflicts_with = "lap_time")]
+    pub(crate) lap_distance: Option<f64>,
+
+    /// Start a new lap every so many minutes
+    #[arg(long, value_name = "MINUTES", value_parser = clap::value_parser!(u32).range(1..))]
+    pub(crate) lap_time: Option<u32>,
+
+    /// File format of the exported workouts
+    #[arg(long, value_enum, default_value_t = FileFormat::Fit)]
+    pub(crate) format: FileFormat,
+
+    /// File name template, `{date}`, `{id}`, `{title}` and `{sport}` are filled in
+    #[arg(long)]
+    pub(crate) filename: Option<String>,
+
+    /// Where the raw XingZhe responses are kept, defaults to `raw` in the output directory
+    #[arg(long)]
+    pub(crate) cache_dir: Option<PathBuf>,
+
+    /// Convert the cached responses again instead of downloading, every cached workout without
+    /// `--workout-id`
+    #[arg(long, conflicts_with_all = ["year", "from", "to", "all"])]
+    pub(crate) offline: bool,
+
+    /// Export the workouts the manifest of the output directory lists again
+    #[arg(long)]
+    pub(crate) force: bool,
+
+    /// How many times a request is retried after a 5xx response or a timeout
+    #[arg(long, default_value_t = 3)]
+    pub(crate) retries: u32,
+
+    /// Output format, `json` prints one JSON line per processed workout
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub(crate) output: OutputFormat,
+
+    /// Config file, defaults to `~/.config/igps_tools/config.toml`
+    #[arg(long, env = config::CONFIG_ENV)]
+    pub(crate) config: Option<PathBuf>,
+}
+
+const DEFAULT_OUTPUT_DIR: &str = "xingzhe/data";
+
+/// Settings shared by every download, merged from the command line and the config file
+#[derive(Debug)]
+struct Context {
+    base_url: String,
+    cookie: HeaderValue,
+    naming: FileNaming,
+    format: FileFormat,
+    ftp: Option<u32>,
+    auto_lap: Option<AutoLap>,
+    cache: RawCache,
+    offline: bool,
+    /// Workouts already exported to the output directory
+    manifest: Manifest,
+    force: bool,
+    retries: u32,
+    output: OutputFormat,
+}
+
+/// A converted workout, or one the manifest says is already done
+#[derive(Debug)]
+struct Exported {
+    file: String,
+    skipped: bool,
+}
+
+/// What to convert
+#[derive(Debug)]
+enum Selection {
+    Workout(u128),
+    /// Every workout of the user in these months
+    Months(u64, Vec<YearMonth>),
+    /// Every workout of the cache directory
+    Cached,
+}
+
+/// Totals printed after converting a range of months
+#[derive(Default, Debug)]
+struct Summary {
+    /// Months with at least one workout
+    months: usize,
+    converted: usize,
+    skipped: usize,
+    failed: usize,
+    /// Months whose workout list couldn't be fetched
+    failed_months: usize,
+}
+
+impl Summary {
+    fn add(&mut self, result: &Result<Exported, String>) {
+        match result {
+            Ok(Exported { skipped: true, .. }) => self.skipped += 1,
+            Ok(_) => self.converted += 1,
+            Err(_) => self.failed += 1,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+pub(crate) enum FileFormat {
+    Fit,
+    /// GPX 1.1 with heart rate, cadence and power extensions
+    Gpx,
+    /// Garmin Training Center
+    Tcx,
+}
+
+impl FileFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            FileFormat::Fit => "fit",
+            FileFormat::Gpx => "gpx",
+            FileFormat::Tcx => "tcx",
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+pub(crate) enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// One line of `--output json`
+#[derive(Serialize, Debug)]
+struct GenerateRecord {
+    workout_id: u128,
+    title: Option<String>,
+    status: &'static str,
+    file: Option<String>,
+    error: Option<String>,
 }
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     let cli = Cli::parse();
-    download(
-        cli.workout_id,
-        cli.year,
-        cli.month,
-        cli.user_id,
-        cli.cookie.as_str(),
+    let config = match Config::load(cli.config.as_deref()) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Err, msg: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let selection = match select(&cli, cli.user_id.or(config.xingzhe.user_id)) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Err, msg: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let auto_lap = match auto_lap(&cli, &config.xingzhe) {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("Err, msg: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let cookie = match cli.cookie.or(config.xingzhe.cookie) {
+        Some(cookie) => cookie,
+        None if cli.offline => String::new(),
+        None => {
+            eprintln!("Err, msg: the XingZhe cookie is not set, use --cookie, XINGZHE_COOKIE or the config file");
+            return ExitCode::FAILURE;
+        }
+    };
+    let Ok(cookie) = HeaderValue::from_str(&cookie) else {
+        eprintln!("Err, msg: the XingZhe cookie isn't a valid header value, e.g. it contains a line break");
+        return ExitCode::FAILURE;
+    };
+    let output_dir = cli
+        .output_dir
+        .or(config.xingzhe.output_dir)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_OUTPUT_DIR));
+    if let Err(e) = fs::create_dir_all(&output_dir) {
+        eprintln!("Err, msg: create output dir {:?} fail: {e}", output_dir);
+        return ExitCode::FAILURE;
+    }
+    let manifest = match Manifest::load(&output_dir) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("Err, msg: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let template = cli
+        .filename
+        .or(config.xingzhe.filename)
+        .unwrap_or_else(|| filename::DEFAULT_TEMPLATE.to_string());
+    let cache_dir = cli
+        .cache_dir
+        .or(config.xingzhe.cache_dir)
+        .unwrap_or_else(|| output_dir.join(cache::DEFAULT_CACHE_DIR));
+    let mut ctx = Context {
+        base_url: config
+            .xingzhe
+            .base_url
+            .unwrap_or_else(|| api::BASE_URL.to_string()),
+        cookie,
+        naming: FileNaming::new(output_dir, template),
+        format: cli.format,
+        ftp: cli.ftp.or(config.xingzhe.ftp),
+        auto_lap,
+        cache: RawCache::new(cache_dir),
+        offline: cli.offline,
+        manifest,
+        force: cli.force,
+        retries: cli.retries,
+        output: cli.output,
+    };
+    let success = download(&mut ctx, selection).await;
+    if success {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Auto-lap of the command line, or else of the config file
+fn auto_lap(cli: &Cli, config: &XingZheConfig) -> Result<Option<AutoLap>, String> {
+    let (distance, time) = match (cli.lap_distance, cli.lap_time) {
+        (None, None) => {
+            if config.lap_distance.is_some() && config.lap_time.is_some() {
+                return Err("xingzhe.lap_distance and xingzhe.lap_time can't both be set".into());
+            }
+            (config.lap_distance, config.lap_time)
+        }
+        cli => cli,
+    };
+    match (distance, time) {
+        (Some(km), _) if km.is_nan() || km <= 0.0 => {
+            Err(format!("invalid lap distance {km}, expected km > 0"))
+        }
+        (Some(km), _) => Ok(Some(AutoLap::Distance(km * 1000.0))),
+        (None, Some(0)) => Err("invalid lap time 0, expected minutes > 0".into()),
+        (None, Some(minutes)) => match minutes.checked_mul(60) {
+            Some(secs) => Ok(Some(AutoLap::Time(secs))),
+            None => Err(format!(
+                "invalid lap time {minutes}, expected at most {} minutes",
+                u32::MAX / 60
+            )),
+        },
+        (None, None) => Ok(None),
+    }
+}
+
+/// The workout or months the command line asks for
+fn select(cli: &Cli, user_id: Option<u64>) -> Result<Selection, String> {
+    if let Some(workout_id) = cli.workout_id {
+        return Ok(Selection::Workout(workout_id));
+    }
+    if cli.offline {
+        return Ok(Selection::Cached);
+    }
+    let months = match (cli.year, cli.month) {
+        (Some(year), Some(month)) => vec![YearMonth { year, month }],
+        (Some(year), None) => YearMonth { year, month: 1 }.until(YearMonth { year, month: 12 }),
+        _ if cli.all || cli.from.is_some() || cli.to.is_some() => {
+            let from = cli.from.unwrap_or(month::FIRST_MONTH);
+            let to = cli.to.unwrap_or_else(YearMonth::current);
+            if from > to {
+                return Err(format!("--from {from} is after --to {to}"));
+            }
+            from.until(to)
+        }
+        _ => {
+            return Err("set --workout-id, --year (and --month), --from/--to or --all".to_string())
+        }
+    };
+    let user_id = user_id
+        .ok_or("the XingZhe user id is not set, use --user-id or the config file".to_string())?;
+    Ok(Selection::Months(user_id, months))
+}
+
+/// Returns `true` when every workout was converted.
+async fn download(ctx: &mut Context, selection: Selection) -> bool {
+    match selection {
+        Selection::Workout(workout_id) => {
+            let result = export(ctx, workout_id).await;
+            print_result(ctx.output, workout_id, None, &result);
+            result.is_ok()
+        }
+        Selection::Months(user_id, months) => {
+            let mut summary = Summary::default();
+            for (i, month) in months.iter().enumerate() {
+                let progress = format!("[{}/{}] {month}", i + 1, months.len());
+                let list = match api::with_retries(ctx.retries, &progress, || {
+                    api::get_month_list(
+                        &ctx.base_url,
+                        user_id,
+                        month.year,
+                        month.month,
+                        &ctx.cookie,
+                    )
+                })
+                .await
+                {
+                    Ok(list) => list,
+                    Err(e) => {
+                        eprintln!("{progress}: get workout list fail, msg: {e}");
+                        summary.failed_months += 1;
+                        continue;
+                    }
+                };
+                if list.is_empty() {
+                    continue;
+                }
+                summary.months += 1;
+                eprintln!("{progress}: {} workouts", list.len());
+                for item in list {
+                    let result = export(ctx, item.workout_id).await;
+                    print_result(ctx.output, item.workout_id, Some(&item.title), &result);
+                    summary.add(&result);
+                }
+            }
+            eprintln!(
+                "{} of {} months had workouts, {} converted, {} skipped, {} failed",
+                summary.months,
+                months.len(),
+                summary.converted,
+                summary.skipped,
+                summary.failed
+            );
+            if summary.failed_months > 0 {
+                eprintln!(
+                    "the workout list of {} months could not be fetched",
+                    summary.failed_months
+                );
+            }
+            summary.failed == 0 && summary.failed_months == 0
+        }
+        Selection::Cached => {
+            let workout_ids = match ctx.cache.workout_ids() {
+                Ok(ids) => ids,
+                Err(e) => {
+                    eprintln!("Err, msg: {e}");
+                    return false;
+                }
+            };
+            let mut summary = Summary::default();
+            for workout_id in workout_ids {
+                let result = export(ctx, workout_id).await;
+                print_result(ctx.output, workout_id, None, &result);
+                summary.add(&result);
+            }
+            eprintln!("{} converted, {} failed", summary.converted, summary.failed);
+            summary.failed == 0
+        }
+    }
+}
+
+/// Convert `workout_id` unless the manifest lists it, and add it to the manifest
+async fn export(ctx: &mut Context, workout_id: u128) -> Result<Exported, String> {
+    let output_dir = ctx.naming.output_dir().to_path_buf();
+    // the point of `--offline` is converting again, e.g. after a converter fix
+    if !ctx.force && !ctx.offline {
+        if let Some(path) = ctx
+            .manifest
+            .exported(&output_dir, workout_id, ctx.format.extension())
+        {
+            return Ok(Exported {
+                file: path.to_string_lossy().to_string(),
+                skipped: true,
+            });
+        }
+    }
+    let file = generate(ctx, workout_id).await?;
+    ctx.manifest
+        .insert(&output_dir, workout_id, Path::new(&file))?;
+    ctx.manifest.save(&output_dir)?;
+    Ok(Exported {
+        file,
+        skipped: false,
+    })
+}
+
+async fn generate(ctx: &mut Context, workout_id: u128) -> Result<String, String> {
+    let (segment_json, points_json) = if ctx.offline {
+        ctx.cache.load(workout_id)?
+    } else {
+        let json = download_json(ctx, workout_id).await?;
+        ctx.cache.save(workout_id, &json.0, &json.1)?;
+        json
+    };
+    // `--force` and `--offline` replace the earlier export instead of writing next to it
+    let extension = ctx.format.extension();
+    let previous = ctx
+        .manifest
+        .exported(ctx.naming.output_dir(), workout_id, extension);
+    let activity = Activity::parse(&segment_json, &points_json, ctx.ftp, ctx.auto_lap)
+        .map_err(|e| e.to_string())?;
+    let path = ctx.naming.path(
+        &NameFields {
+            workout_id,
+            title: &activity.title,
+            sport: activity.sport,
+            start_time: activity.start_time,
+        },
+        extension,
+        previous.as_deref(),
+    );
+    // the earlier export stays until the new file is complete
+    let partial = path.with_extension(format!("{extension}.part"));
+    match ctx.format {
+        FileFormat::Fit => build_fit::write_fit(&activity, &partial),
+        FileFormat::Gpx => gpx::write_gpx(&activity, &partial),
+        FileFormat::Tcx => tcx::write_tcx(&activity, &partial),
+    }
+    .map_err(|e| {
+        let _ = fs::remove_file(&partial);
+        e.to_string()
+    })?;
+    fs::rename(&partial, &path).map_err(|e| format!("rename {:?} fail: {e}", partial))?;
+    if let Some(previous) = previous.filter(|previous| *previous != path) {
+        fs::remove_file(&previous).map_err(|e| format!("remove {:?} fail: {e}", previous))?;
+    }
+    Ok(path.to_string_lossy().to_string())
+}
+
+/// The `segment` and `points` responses of `workout_id`
+async fn download_json(ctx: &Context, workout_id: u128) -> Result<(String, String), String> {
+    let segment_json = api::with_retries(
+        ctx.retries,
+        &format!("get segment of workout_id: {workout_id}"),
+        || api::segment(&ctx.base_url, workout_id, &ctx.cookie),
+    )
+    .await
+    .map_err(|e| format!("get segment fail: {e}"))?;
+    let points_json = api::with_retries(
+        ctx.retries,
+        &format!("get points of workout_id: {workout_id}"),
+        || api::points(&ctx.base_url, workout_id, &ctx.cookie),
     )
-    .await;
+    .await
+    .map_err(|e| format!("get points fail: {e}"))?;
+    Ok((segment_json, points_json))
 }
 
-async fn download(
-    workout_id: Option<u128>,
-    year: Option<u32>,
-    month: Option<u32>,
-    user_id: u64,
-    cookie: &str,
+fn print_result(
+    output: OutputFormat,
+    workout_id: u128,
+    title: Option<&str>,
+    result: &Result<Exported, String>,
 ) {
-    match (workout_id, year, month) {
-        (Some(workout_id), _, _) => {
-            generate(workout_id, cookie).await;
-            println!("get workout_id: {} success.", workout_id);
-        }
-        (None, Some(year), Some(month)) => {
-            let list = api::get_month_list(user_id, year, month, cookie).await;
-            for item in list {
-                generate(item.workout_id, cookie).await;
-                println!(
-                    "get {}-{}, workout_id: {}, title: {} success.",
-                    year, month, item.workout_id, item.title
+    match output {
+        OutputFormat::Text => match (result, title) {
+            (
+                Ok(Exported {
+                    file,
+                    skipped: true,
+                }),
+                _,
+            ) => println!(
+                "skip workout_id: {}, already exported, file: {}.",
+                workout_id, file
+            ),
+            (Ok(Exported { file, .. }), Some(title)) => println!(
+                "get workout_id: {}, title: {} success, file: {}.",
+                workout_id, title, file
+            ),
+            (Ok(Exported { file, .. }), None) => {
+                println!("get workout_id: {} success, file: {}.", workout_id, file)
+            }
+            (Err(e), _) => eprintln!("get workout_id: {} fail, msg: {}", workout_id, e),
+        },
+        OutputFormat::Json => {
+            let record = GenerateRecord {
+                workout_id,
+                title: title.map(|t| t.to_string()),
+                status: match result {
+                    Ok(Exported { skipped: true, .. }) => "skipped",
+                    Ok(_) => "ok",
+                    Err(_) => "failed",
+                },
+                file: result.as_ref().ok().map(|e| e.file.clone()),
+                error: result.as_ref().err().cloned(),
+            };
+            match serde_json::to_string(&record) {
+                Ok(line) => println!("{line}"),
+                Err(e) => eprintln!("workout_id: {workout_id}, serialize record fail: {e}"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{temp_dir, POINTS_JSON, SEGMENT_JSON};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn lap(args: &[&str], config: &XingZheConfig) -> Result<Option<AutoLap>, String> {
+        let cli = Cli::try_parse_from([&["xingzhe"], args].concat()).unwrap();
+        auto_lap(&cli, config)
+    }
+
+    #[test]
+    fn lap_times_are_minutes() {
+        let config = XingZheConfig::default();
+        assert!(matches!(
+            lap(&["--lap-time", "5"], &config),
+            Ok(Some(AutoLap::Time(300)))
+        ));
+        assert!(matches!(
+            lap(&["--lap-time", "71582788"], &config),
+            Ok(Some(AutoLap::Time(4_294_967_280)))
+        ));
+        // more seconds than a u32 holds
+        assert!(lap(&["--lap-time", "71582789"], &config).is_err());
+        let config = XingZheConfig {
+            lap_time: Some(u32::MAX),
+            ..Default::default()
+        };
+        assert!(lap(&[], &config).is_err());
+    }
+
+    /// Answer the `segment` and `points` requests of any workout with the fixture ride, returns
+    /// the base URL and the number of requests served.
+    async fn serve() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let served = Arc::new(AtomicUsize::new(0));
+        let counter = served.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                }
+                counter.fetch_add(1, Ordering::SeqCst);
+                let body = if request.starts_with(b"GET /api/v4/segment_workout/") {
+                    SEGMENT_JSON
+                } else {
+                    POINTS_JSON
+                };
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                    body.len()
                 );
+                socket.write_all(response.as_bytes()).await.unwrap();
             }
+        });
+        (url, served)
+    }
+
+    fn context(base_url: String, output_dir: &Path, format: FileFormat) -> Context {
+        Context {
+            base_url,
+            cookie: HeaderValue::from_static("sessionid=test"),
+            naming: FileNaming::new(output_dir.to_path_buf(), filename::DEFAULT_TEMPLATE.into()),
+            format,
+            ftp: None,
+            auto_lap: None,
+            cache: RawCache::new(output_dir.join(cache::DEFAULT_CACHE_DIR)),
+            offline: false,
+            manifest: Manifest::load(output_dir).unwrap(),
+            force: false,
+            retries: 0,
+            output: OutputFormat::Text,
         }
-        _ => {
-            panic!("workout id or year/month maybe not set!")
+    }
+
+    #[tokio::test]
+    async fn a_second_run_skips_the_exported_workout() {
+        let (url, served) = serve().await;
+        let dir = temp_dir("export-skip");
+        let first = export(&mut context(url.clone(), &dir, FileFormat::Fit), 42)
+            .await
+            .unwrap();
+        assert!(!first.skipped);
+        assert_eq!(served.load(Ordering::SeqCst), 2);
+        // a new run reads the manifest the first one left
+        let second = export(&mut context(url, &dir, FileFormat::Fit), 42)
+            .await
+            .unwrap();
+        assert!(second.skipped);
+        assert_eq!(second.file, first.file);
+        assert_eq!(served.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn force_exports_again_in_place_of_the_earlier_file() {
+        let (url, served) = serve().await;
+        let dir = temp_dir("export-force");
+        let first = export(&mut context(url.clone(), &dir, FileFormat::Fit), 42)
+            .await
+            .unwrap();
+        let mut ctx = context(url, &dir, FileFormat::Fit);
+        ctx.force = true;
+        let second = export(&mut ctx, 42).await.unwrap();
+        assert!(!second.skipped);
+        assert_eq!(second.file, first.file);
+        assert_eq!(served.load(Ordering::SeqCst), 4);
+        assert!(!dir.join("Morning ride (2).fit").exists());
+    }
+
+    #[tokio::test]
+    async fn every_format_is_exported_once() {
+        let (url, served) = serve().await;
+        let dir = temp_dir("export-formats");
+        let fit = export(&mut context(url.clone(), &dir, FileFormat::Fit), 42)
+            .await
+            .unwrap();
+        let gpx = export(&mut context(url.clone(), &dir, FileFormat::Gpx), 42)
+            .await
+            .unwrap();
+        assert!(!gpx.skipped);
+        assert_eq!(gpx.file, dir.join("Morning ride.gpx").to_string_lossy());
+        assert_eq!(served.load(Ordering::SeqCst), 4);
+        for format in [FileFormat::Fit, FileFormat::Gpx] {
+            assert!(
+                export(&mut context(url.clone(), &dir, format), 42)
+                    .await
+                    .unwrap()
+                    .skipped
+            );
         }
+        assert!(Path::new(&fit.file).exists());
+        assert_eq!(served.load(Ordering::SeqCst), 4);
     }
-}
 
-async fn generate(workout_id: u128, cookie: &str) {
-    let segment_json = api::segment(workout_id, cookie).await.unwrap_or_default();
-    let points_json = api::points(workout_id, cookie).await.unwrap_or_default();
-    build_fit::generate_fit(segment_json, points_json);
+    #[tokio::test]
+    async fn offline_converts_the_cached_responses_again() {
+        let (url, served) = serve().await;
+        let dir = temp_dir("export-offline");
+        let online = export(&mut context(url, &dir, FileFormat::Fit), 42)
+            .await
+            .unwrap();
+        let fit = fs::read(&online.file).unwrap();
+        // nothing listens there, XingZhe isn't asked
+        let mut ctx = context("http://127.0.0.1:9".to_string(), &dir, FileFormat::Fit);
+        ctx.offline = true;
+        let offline = export(&mut ctx, 42).await.unwrap();
+        assert!(!offline.skipped);
+        assert_eq!(offline.file, online.file);
+        assert_eq!(fs::read(&offline.file).unwrap(), fit);
+        assert!(download(&mut ctx, Selection::Cached).await);
+        assert_eq!(served.load(Ordering::SeqCst), 2);
+    }
 }
@@ -0,0 +1,166 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Kept in the output directory, next to the FIT files
+pub(crate) const MANIFEST_FILE: &str = "manifest.json";
+
+/// The workouts already exported to an output directory, so an interrupted export can resume
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub(crate) struct Manifest {
+    /// By workout id, one entry per exported file format
+    workouts: BTreeMap<u128, Vec<ManifestEntry>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct ManifestEntry {
+    /// Relative to the output directory
+    pub(crate) file: PathBuf,
+    /// SHA-256 of the file, hex encoded
+    pub(crate) sha256: String,
+}
+
+impl Manifest {
+    /// Read the manifest of `output_dir`, a missing one is empty
+    pub(crate) fn load(output_dir: &Path) -> Result<Manifest, String> {
+        let path = output_dir.join(MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(Manifest::default());
+        }
+        let content =
+            fs::read_to_string(&path).map_err(|e| format!("read {:?} fail: {e}", path))?;
+        serde_json::from_str(&content).map_err(|e| format!("parse {:?} fail: {e}", path))
+    }
+
+    /// Write the manifest to a temporary file first, an interrupted write keeps the old one
+    pub(crate) fn save(&self, output_dir: &Path) -> Result<(), String> {
+        let path = output_dir.join(MANIFEST_FILE);
+        let tmp = output_dir.join(format!("{MANIFEST_FILE}.tmp"));
+        let content = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(&tmp, content).map_err(|e| format!("write {:?} fail: {e}", tmp))?;
+        fs::rename(&tmp, &path).map_err(|e| format!("write {:?} fail: {e}", path))
+    }
+
+    /// The `extension` file `workout_id` was exported to, when it's still there and unchanged
+    pub(crate) fn exported(
+        &self,
+        output_dir: &Path,
+        workout_id: u128,
+        extension: &str,
+    ) -> Option<PathBuf> {
+        let entry = self
+            .workouts
+            .get(&workout_id)?
+            .iter()
+            .find(|entry| file_extension(&entry.file) == extension)?;
+        let path = output_dir.join(&entry.file);
+        match checksum(&path) {
+            Ok(sha256) if sha256 == entry.sha256 => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Record `path` as the export of `workout_id` in its file format
+    pub(crate) fn insert(
+        &mut self,
+        output_dir: &Path,
+        workout_id: u128,
+        path: &Path,
+    ) -> Result<(), String> {
+        let sha256 = checksum(path).map_err(|e| format!("read {:?} fail: {e}", path))?;
+        let file = path.strip_prefix(output_dir).unwrap_or(path).to_path_buf();
+        let entries = self.workouts.entry(workout_id).or_default();
+        entries.retain(|entry| file_extension(&entry.file) != file_extension(&file));
+        entries.push(ManifestEntry { file, sha256 });
+        Ok(())
+    }
+}
+
+fn file_extension(path: &Path) -> String {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+/// SHA-256 of the file at `path`, hex encoded
+pub(crate) fn checksum(path: &Path) -> std::io::Result<String> {
+    let content = fs::read(path)?;
+    Ok(format!("{:x}", Sha256::digest(content)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::temp_dir;
+
+    #[test]
+    fn exported_files_are_found_by_format() {
+        let dir = temp_dir("manifest-formats");
+        fs::write(dir.join("Ride.fit"), "fit").unwrap();
+        fs::write(dir.join("Ride.gpx"), "gpx").unwrap();
+        let mut manifest = Manifest::default();
+        manifest.insert(&dir, 42, &dir.join("Ride.fit")).unwrap();
+        manifest.insert(&dir, 42, &dir.join("Ride.gpx")).unwrap();
+        assert_eq!(
+            manifest.exported(&dir, 42, "fit"),
+            Some(dir.join("Ride.fit"))
+        );
+        assert_eq!(
+            manifest.exported(&dir, 42, "gpx"),
+            Some(dir.join("Ride.gpx"))
+        );
+        assert_eq!(manifest.exported(&dir, 42, "tcx"), None);
+        assert_eq!(manifest.exported(&dir, 43, "fit"), None);
+    }
+
+    #[test]
+    fn a_new_export_replaces_the_entry_of_its_format() {
+        let dir = temp_dir("manifest-replace");
+        fs::write(dir.join("Ride.fit"), "fit").unwrap();
+        fs::write(dir.join("Ride (2).fit"), "fit 2").unwrap();
+        let mut manifest = Manifest::default();
+        manifest.insert(&dir, 42, &dir.join("Ride.fit")).unwrap();
+        manifest
+            .insert(&dir, 42, &dir.join("Ride (2).fit"))
+            .unwrap();
+        assert_eq!(manifest.workouts[&42].len(), 1);
+        assert_eq!(
+            manifest.exported(&dir, 42, "fit"),
+            Some(dir.join("Ride (2).fit"))
+        );
+    }
+
+    #[test]
+    fn changed_or_missing_files_are_not_exported() {
+        let dir = temp_dir("manifest-changed");
+        fs::write(dir.join("Ride.fit"), "fit").unwrap();
+        fs::write(dir.join("Ride.gpx"), "gpx").unwrap();
+        let mut manifest = Manifest::default();
+        manifest.insert(&dir, 42, &dir.join("Ride.fit")).unwrap();
+        manifest.insert(&dir, 42, &dir.join("Ride.gpx")).unwrap();
+        fs::write(dir.join("Ride.fit"), "changed").unwrap();
+        fs::remove_file(dir.join("Ride.gpx")).unwrap();
+        assert_eq!(manifest.exported(&dir, 42, "fit"), None);
+        assert_eq!(manifest.exported(&dir, 42, "gpx"), None);
+    }
+
+    #[test]
+    fn the_manifest_is_saved_in_the_output_directory() {
+        let dir = temp_dir("manifest-save");
+        assert!(Manifest::load(&dir).unwrap().workouts.is_empty());
+        fs::write(dir.join("Ride.fit"), "fit").unwrap();
+        let mut manifest = Manifest::default();
+        manifest.insert(&dir, 42, &dir.join("Ride.fit")).unwrap();
+        manifest.save(&dir).unwrap();
+        assert!(!dir.join(format!("{MANIFEST_FILE}.tmp")).exists());
+        let manifest = Manifest::load(&dir).unwrap();
+        // relative to the output directory, so it can be moved
+        assert_eq!(manifest.workouts[&42][0].file, PathBuf::from("Ride.fit"));
+        assert_eq!(
+            manifest.exported(&dir, 42, "fit"),
+            Some(dir.join("Ride.fit"))
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// XingZhe times are shown in China Standard Time, dates and months follow them
+const UTC_OFFSET_SECS: i64 = 8 * 3600;
+
+/// The first month `--all` looks at, XingZhe didn't exist before
+pub(crate) const FIRST_MONTH: YearMonth = YearMonth {
+    year: 2012,
+    month: 1,
+};
+
+/// A calendar month, `YYYY-MM` on the command line
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub(crate) struct YearMonth {
+    pub(crate) year: u32,
+    pub(crate) month: u32,
+}
+
+impl YearMonth {
+    /// The month it is now
+    pub(crate) fn current() -> YearMonth {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let (year, month, _) = date(millis);
+        YearMonth {
+            year: year as u32,
+            month,
+        }
+    }
+
+    pub(crate) fn next(self) -> YearMonth {
+        if self.month == 12 {
+            YearMonth {
+                year: self.year + 1,
+                month: 1,
+            }
+        } else {
+            YearMonth {
+                year: self.year,
+                month: self.month + 1,
+            }
+        }
+    }
+
+    /// Every month from `self` to `to`, both included
+    pub(crate) fn until(self, to: YearMonth) -> Vec<YearMonth> {
+        let mut months = vec![];
+        let mut month = self;
+        while month <= to {
+            months.push(month);
+            month = month.next();
+        }
+        months
+    }
+}
+
+impl FromStr for YearMonth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid month {s:?}, expected YYYY-MM");
+        let (year, month) = s.split_once('-').ok_or_else(invalid)?;
+        let year = year.parse::<u32>().map_err(|_| invalid())?;
+        let month = month.parse::<u32>().map_err(|_| invalid())?;
+        if !(1..=12).contains(&month) {
+            return Err(invalid());
+        }
+        Ok(YearMonth { year, month })
+    }
+}
+
+impl Display for YearMonth {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04}-{:02}", self.year, self.month)
+    }
+}
+
+/// Year, month and day of a unix time in milliseconds
+pub(crate) fn date(millis: u64) -> (i64, u32, u32) {
+    civil_from_days((millis as i64 / 1000 + UTC_OFFSET_SECS).div_euclid(86400))
+}
+
+/// ISO 8601 UTC time of a unix time in seconds, as GPX and TCX want it
+pub(crate) fn format_utc(secs: u32) -> String {
+    let secs = secs as i64;
+    let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+    let time = secs.rem_euclid(86400);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month as u32, day as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn month(year: u32, month: u32) -> YearMonth {
+        YearMonth { year, month }
+    }
+
+    #[test]
+    fn until_crosses_the_year() {
+        assert_eq!(
+            month(2022, 11).until(month(2023, 2)),
+            vec![
+                month(2022, 11),
+                month(2022, 12),
+                month(2023, 1),
+                month(2023, 2)
+            ]
+        );
+        assert_eq!(month(2023, 5).until(month(2023, 5)), vec![month(2023, 5)]);
+        assert_eq!(month(2023, 6).until(month(2023, 5)), vec![]);
+    }
+
+    #[test]
+    fn from_str() {
+        assert_eq!("2019-01".parse(), Ok(month(2019, 1)));
+        assert_eq!("2023-12".parse(), Ok(month(2023, 12)));
+        assert_eq!(month(2019, 1).to_string(), "2019-01");
+        for invalid in ["2023", "2023-13", "2023-00", "2023-1x", "-05", ""] {
+            assert!(invalid.parse::<YearMonth>().is_err(), "{invalid:?}");
+        }
+    }
+}
@@ -0,0 +1,340 @@
+use crate::activity::{Record, WorkoutSession};
+use std::ops::Range;
+
+/// Slower than this (m/s) counts as standing still
+pub(crate) const MOVING_SPEED: f32 = 0.5;
+
+/// Samples further apart than this (seconds) have a pause between them
+pub(crate) const MAX_SAMPLE_GAP: u32 = 10;
+
+/// Standing still shorter than this (seconds) keeps the timer running, e.g. at a junction
+const MIN_PAUSE: u32 = 5;
+
+/// Power is averaged over this many seconds for the normalized power
+const NP_WINDOW: usize = 30;
+
+/// Altitude changes smaller than this (meters) are noise and don't count as ascent or descent
+const ALTITUDE_HYSTERESIS: f32 = 2.0;
+
+/// Totals of a stretch of records, the whole session or one lap
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Summary {
+    /// Unix time in seconds
+    pub(crate) start_time: u32,
+    /// Unix time in seconds
+    pub(crate) end_time: u32,
+    /// Seconds from the first to the last record
+    pub(crate) elapsed_time: u32,
+    /// Seconds the timer ran, the elapsed time without pauses
+    pub(crate) timer_time: u32,
+    /// Seconds faster than `MOVING_SPEED`, or pedaling on a trainer
+    pub(crate) moving_time: u32,
+    /// Meters
+    pub(crate) distance: f64,
+    /// Meters per second while moving
+    pub(crate) avg_speed: f32,
+    pub(crate) max_speed: f32,
+    /// Kilocalories
+    pub(crate) calories: u32,
+    pub(crate) min_heart_rate: Option<u8>,
+    pub(crate) avg_heart_rate: Option<u8>,
+    pub(crate) max_heart_rate: Option<u8>,
+    /// Zeros (coasting) left out
+    pub(crate) avg_cadence: Option<u8>,
+    pub(crate) max_cadence: Option<u8>,
+    pub(crate) avg_power: Option<u16>,
+    pub(crate) max_power: Option<u16>,
+    pub(crate) normalized_power: Option<f64>,
+    /// Needs the FTP
+    pub(crate) intensity_factor: Option<f64>,
+    /// Needs the FTP
+    pub(crate) training_stress_score: Option<f64>,
+    /// Meters
+    pub(crate) ascent: f64,
+    pub(crate) descent: f64,
+    pub(crate) min_altitude: Option<f32>,
+    pub(crate) avg_altitude: Option<f32>,
+    pub(crate) max_altitude: Option<f32>,
+}
+
+impl Summary {
+    /// Calculate the totals of `records`, IF and TSS when `ftp` is known
+    pub(crate) fn from_records(records: &[Record], ftp: Option<u32>) -> Summary {
+        Summary::from_range(records, 0..records.len(), ftp)
+    }
+
+    /// Like `from_records` for the records in `range`. The times, the distance and the ascent
+    /// count from the record before, where the lap before ended, its samples belong to that lap.
+    pub(crate) fn from_range(all: &[Record], range: Range<usize>, ftp: Option<u32>) -> Summary {
+        let stretch = &all[range.start.saturating_sub(1)..range.end];
+        let records = &all[range];
+        let (Some(first), Some(last)) = (stretch.first(), records.last()) else {
+            return Summary::default();
+        };
+        let elapsed_time = last.timestamp.saturating_sub(first.timestamp);
+        let mut summary = Summary {
+            start_time: first.timestamp,
+            end_time: last.timestamp,
+            elapsed_time,
+            timer_time: elapsed_time
+                .saturating_sub(pauses(stretch).iter().map(|p| p.end - p.start).sum::<u32>()),
+            distance: last.distance - first.distance,
+            ..Summary::default()
+        };
+
+        // every record holds for the seconds since the one before, unless there's a pause between
+        let mut watts = vec![];
+        let base = stretch.len() - records.len();
+        for (i, record) in stretch.iter().enumerate().skip(base) {
+            let dt = match i {
+                0 => 0,
+                _ => record.timestamp.saturating_sub(stretch[i - 1].timestamp),
+            };
+            let secs = if i == 0 || dt > MAX_SAMPLE_GAP { 1 } else { dt };
+            if !standing(record) && dt <= MAX_SAMPLE_GAP {
+                summary.moving_time += dt;
+            }
+            // a sample without power counts as zero watts
+            let power = record.power.unwrap_or(0) as f64;
+            watts.extend(std::iter::repeat_n(power, secs as usize));
+        }
+
+        summary.max_speed = records.iter().map(|r| r.speed).fold(0.0, f32::max);
+        if summary.moving_time > 0 {
+            summary.avg_speed = (summary.distance / summary.moving_time as f64) as f32;
+        }
+
+        let heart_rates: Vec<u8> = records.iter().filter_map(|r| r.heart_rate).collect();
+        summary.min_heart_rate = heart_rates.iter().copied().min();
+        summary.max_heart_rate = heart_rates.iter().copied().max();
+        summary.avg_heart_rate = average(heart_rates.iter().map(|hr| *hr as f64)).map(|a| a as u8);
+
+        let cadences = || records.iter().filter_map(|r| r.cadence).filter(|c| *c > 0);
+        summary.max_cadence = cadences().max();
+        summary.avg_cadence = average(cadences().map(|c| c as f64)).map(|a| a as u8);
+
+        if records.iter().any(|r| r.power.is_some_and(|p| p > 0)) {
+            summary.max_power = records.iter().filter_map(|r| r.power).max();
+            summary.avg_power = average(watts.iter().copied()).map(|a| a as u16);
+            // one watt for one second is one joule, the body burns about as many kcal as the
+            // legs put out kJ
+            summary.calories = (watts.iter().sum::<f64>() / 1000.0) as u32;
+            summary.normalized_power = normalized_power(&watts);
+        }
+        if let (Some(np), Some(ftp)) = (summary.normalized_power, ftp.filter(|f| *f > 0)) {
+            let intensity_factor = np / ftp as f64;
+            summary.intensity_factor = Some(intensity_factor);
+            summary.training_stress_score =
+                Some(watts.len() as f64 * np * intensity_factor / (ftp as f64 * 3600.0) * 100.0);
+        }
+
+        // samples without altitude are left out
+        let mut altitudes = stretch.iter().filter_map(|r| r.altitude);
+        if let Some(mut reference) = altitudes.next() {
+            for altitude in altitudes {
+                let change = altitude - reference;
+                if change >= ALTITUDE_HYSTERESIS {
+                    summary.ascent += change as f64;
+                    reference = altitude;
+                } else if change <= -ALTITUDE_HYSTERESIS {
+                    summary.descent -= change as f64;
+                    reference = altitude;
+                }
+            }
+        }
+        let altitudes = || records.iter().filter_map(|r| r.altitude);
+        summary.min_altitude = altitudes().reduce(f32::min);
+        summary.max_altitude = altitudes().reduce(f32::max);
+        summary.avg_altitude = average(altitudes().map(|a| a as f64)).map(|a| a as f32);
+        summary
+    }
+
+    /// Fill in what the records don't have from the totals XingZhe calculated
+    pub(crate) fn or_session(mut self, session: &WorkoutSession) -> Summary {
+        let positive = |v: f64| Some(v).filter(|v| *v > 0.0);
+        if self.elapsed_time == 0 {
+            self.start_time = (session.start_time / 1000) as u32;
+            self.end_time = (session.end_time / 1000) as u32;
+            self.elapsed_time = self.end_time.saturating_sub(self.start_time);
+            self.timer_time = self.elapsed_time;
+            self.moving_time = self.elapsed_time;
+        }
+        if self.distance <= 0.0 {
+            self.distance = session.distance as f64;
+        }
+        if self.max_speed <= 0.0 {
+            self.max_speed = session.max_speed / 3.6;
+        }
+        if self.avg_speed <= 0.0 {
+            self.avg_speed = session.avg_speed / 3.6;
+        }
+        if self.calories == 0 {
+            self.calories = session.calories / 1000;
+        }
+        self.avg_heart_rate = self
+            .avg_heart_rate
+            .or(session.avg_heartrate.filter(|v| *v > 0));
+        self.max_heart_rate = self
+            .max_heart_rate
+            .or(session.max_heartrate.filter(|v| *v > 0));
+        self.avg_cadence = self.avg_cadence.or(session.avg_cadence.filter(|v| *v > 0));
+        self.max_cadence = self.max_cadence.or(session.max_cadence.filter(|v| *v > 0));
+        self.avg_power = self.avg_power.or(session.power_avg.filter(|v| *v > 0));
+        self.max_power = self.max_power.or(session.power_max.filter(|v| *v > 0));
+        self.normalized_power = self.normalized_power.or(positive(session.power_np as f64));
+        self.intensity_factor = self.intensity_factor.or(positive(session.power_if as f64));
+        self.training_stress_score = self
+            .training_stress_score
+            .or(positive(session.power_tss as f64));
+        if self.ascent <= 0.0 && self.descent <= 0.0 {
+            self.ascent = session.elevation_gain.unwrap_or(0) as f64;
+            self.descent = session.elevation_loss.unwrap_or(0) as f64;
+        }
+        self.max_altitude = self.max_altitude.or(session.max_altitude.map(f32::from));
+        self
+    }
+}
+
+/// When the timer stopped and started again, a pause for every gap between the samples and
+/// every stretch of standing still
+pub(crate) fn pauses(records: &[Record]) -> Vec<Range<u32>> {
+    let mut pauses: Vec<Range<u32>> = records
+        .windows(2)
+        .filter(|w| w[1].timestamp.saturating_sub(w[0].timestamp) > MAX_SAMPLE_GAP)
+        .map(|w| w[0].timestamp..w[1].timestamp)
+        .collect();
+    let mut next = 0;
+    for stretch in records.chunk_by(|a, b| standing(a) == standing(b)) {
+        next += stretch.len();
+        if !standing(&stretch[0]) {
+            continue;
+        }
+        // the timer starts again with the next sample, or not at all at the end of the ride
+        let since = stretch[0].timestamp;
+        let until = records.get(next).unwrap_or(&stretch[stretch.len() - 1]);
+        if until.timestamp.saturating_sub(since) >= MIN_PAUSE {
+            pauses.push(since..until.timestamp);
+        }
+    }
+    pauses.sort_by_key(|p| p.start);
+
+    let mut merged: Vec<Range<u32>> = vec![];
+    for pause in pauses {
+        match merged.last_mut() {
+            Some(before) if before.end >= pause.start => before.end = before.end.max(pause.end),
+            _ => merged.push(pause),
+        }
+    }
+    merged
+}
+
+/// Slower than `MOVING_SPEED` without cadence or power, a steep climb at walking pace still moves
+fn standing(record: &Record) -> bool {
+    record.speed < MOVING_SPEED
+        && record.cadence.unwrap_or(0) == 0
+        && record.power.unwrap_or(0) == 0
+}
+
+fn average(values: impl Iterator<Item = f64>) -> Option<f64> {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), v| (sum + v, count + 1));
+    (count > 0).then(|| sum / count as f64)
+}
+
+/// Fourth root of the mean of the fourth powers of the 30 second rolling average, `None` for
+/// rides shorter than the window
+fn normalized_power(watts: &[f64]) -> Option<f64> {
+    if watts.len() < NP_WINDOW {
+        return None;
+    }
+    let mut window: f64 = watts[..NP_WINDOW].iter().sum();
+    let mut sum = (window / NP_WINDOW as f64).powi(4);
+    for i in NP_WINDOW..watts.len() {
+        window += watts[i] - watts[i - NP_WINDOW];
+        sum += (window / NP_WINDOW as f64).powi(4);
+    }
+    let count = watts.len() - NP_WINDOW + 1;
+    Some((sum / count as f64).powf(0.25))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::record;
+
+    /// `(seconds, m/s, cadence, watts)` samples, 1 s apart unless the seconds skip ahead
+    fn records(samples: &[(u32, f32, u8, u16)]) -> Vec<Record> {
+        samples
+            .iter()
+            .map(|&(timestamp, speed, cadence, power)| record(timestamp, speed, cadence, power))
+            .collect()
+    }
+
+    fn riding(from: u32, to: u32) -> Vec<(u32, f32, u8, u16)> {
+        (from..to).map(|t| (t, 8.0, 85, 200)).collect()
+    }
+
+    fn standing_still(from: u32, to: u32) -> Vec<(u32, f32, u8, u16)> {
+        (from..to).map(|t| (t, 0.0, 0, 0)).collect()
+    }
+
+    #[test]
+    fn a_gap_between_samples_is_a_pause() {
+        let samples = [riding(0, 10), riding(40, 50)].concat();
+        assert_eq!(pauses(&records(&samples)), vec![9..40]);
+        // up to MAX_SAMPLE_GAP the timer keeps running
+        let samples = [riding(0, 10), riding(19, 30)].concat();
+        assert_eq!(pauses(&records(&samples)), vec![]);
+    }
+
+    #[test]
+    fn standing_still_is_a_pause() {
+        let samples = [riding(0, 10), standing_still(10, 20), riding(20, 30)].concat();
+        assert_eq!(pauses(&records(&samples)), vec![10..20]);
+        // a short stop, e.g. at a junction
+        let samples = [riding(0, 10), standing_still(10, 13), riding(13, 30)].concat();
+        assert_eq!(pauses(&records(&samples)), vec![]);
+        // standing still until the end of the ride
+        let samples = [riding(0, 10), standing_still(10, 20)].concat();
+        assert_eq!(pauses(&records(&samples)), vec![10..19]);
+    }
+
+    #[test]
+    fn pedaling_or_pushing_is_no_pause() {
+        let climbing: Vec<_> = (10..20).map(|t| (t, 0.3, 40, 0)).collect();
+        let samples = [riding(0, 10), climbing, riding(20, 30)].concat();
+        assert_eq!(pauses(&records(&samples)), vec![]);
+        let pushing: Vec<_> = (10..20).map(|t| (t, 0.0, 0, 150)).collect();
+        let samples = [riding(0, 10), pushing, riding(20, 30)].concat();
+        assert_eq!(pauses(&records(&samples)), vec![]);
+    }
+
+    #[test]
+    fn overlapping_pauses_are_merged() {
+        let samples = [
+            riding(0, 10),
+            standing_still(10, 20),
+            standing_still(60, 70),
+            riding(70, 80),
+        ]
+        .concat();
+        let records = records(&samples);
+        assert_eq!(pauses(&records), vec![10..70]);
+        let summary = Summary::from_records(&records, None);
+        assert_eq!(summary.elapsed_time, 79);
+        assert_eq!(summary.timer_time, 79 - 60);
+    }
+
+    #[test]
+    fn samples_without_altitude_are_left_out() {
+        let mut records = records(&riding(0, 4));
+        let altitudes = [None, Some(100.0), None, Some(104.0)];
+        for (record, altitude) in records.iter_mut().zip(altitudes) {
+            record.altitude = altitude;
+        }
+        let summary = Summary::from_records(&records, None);
+        assert_eq!(summary.min_altitude, Some(100.0));
+        assert_eq!(summary.avg_altitude, Some(102.0));
+        assert_eq!(summary.ascent, 4.0);
+        assert_eq!(summary.descent, 0.0);
+    }
+}
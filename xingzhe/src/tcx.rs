@@ -0,0 +1,226 @@
+use crate::activity::{Activity, Record};
+use crate::gpx::escape;
+use crate::lap::{Lap, LapTrigger};
+use crate::month::format_utc;
+use std::error::Error;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+/// Write `activity` as a Garmin Training Center (TCX) file to `path`, with the laps of the FIT file.
+///
+/// Speed and power of every trackpoint and the average speed and power of the lap go into the
+/// `ActivityExtension` elements.
+pub(crate) fn write_tcx(activity: &Activity, path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut tcx = String::new();
+    let _ = writeln!(tcx, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(
+        tcx,
+        r#"<TrainingCenterDatabase xmlns="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2" xmlns:ns3="http://www.garmin.com/xmlschemas/ActivityExtension/v2" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:schemaLocation="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2 http://www.garmin.com/xmlschemas/TrainingCenterDatabasev2.xsd">"#
+    );
+    let _ = writeln!(tcx, "  <Activities>");
+    let _ = writeln!(
+        tcx,
+        r#"    <Activity Sport="{}">"#,
+        tcx_sport(activity.sport)
+    );
+    let _ = writeln!(tcx, "      <Id>{}</Id>", format_utc(activity.start_time));
+    for lap in activity.laps() {
+        write_lap(&mut tcx, &lap, &activity.records[lap.records.clone()]);
+    }
+    let _ = writeln!(tcx, "      <Notes>{}</Notes>", escape(&activity.title));
+    let _ = writeln!(tcx, "    </Activity>");
+    let _ = writeln!(tcx, "  </Activities>");
+    let _ = writeln!(tcx, "</TrainingCenterDatabase>");
+    fs::write(path, tcx)?;
+    Ok(())
+}
+
+fn write_lap(tcx: &mut String, lap: &Lap, records: &[Record]) {
+    let trigger = match lap.trigger {
+        LapTrigger::Distance => "Distance",
+        LapTrigger::Time => "Time",
+        LapTrigger::SessionEnd => "Manual",
+    };
+    let lap = &lap.summary;
+    let _ = writeln!(
+        tcx,
+        r#"      <Lap StartTime="{}">"#,
+        format_utc(lap.start_time)
+    );
+    let _ = writeln!(
+        tcx,
+        "        <TotalTimeSeconds>{}</TotalTimeSeconds>",
+        lap.timer_time
+    );
+    let _ = writeln!(
+        tcx,
+        "        <DistanceMeters>{:.1}</DistanceMeters>",
+        lap.distance
+    );
+    let _ = writeln!(
+        tcx,
+        "        <MaximumSpeed>{:.3}</MaximumSpeed>",
+        lap.max_speed
+    );
+    let _ = writeln!(tcx, "        <Calories>{}</Calories>", lap.calories);
+    if let Some(avg_heart_rate) = lap.avg_heart_rate {
+        let _ = writeln!(
+            tcx,
+            "        <AverageHeartRateBpm><Value>{}</Value></AverageHeartRateBpm>",
+            avg_heart_rate
+        );
+    }
+    if let Some(max_heart_rate) = lap.max_heart_rate {
+        let _ = writeln!(
+            tcx,
+            "        <MaximumHeartRateBpm><Value>{}</Value></MaximumHeartRateBpm>",
+            max_heart_rate
+        );
+    }
+    let _ = writeln!(tcx, "        <Intensity>Active</Intensity>");
+    if let Some(avg_cadence) = lap.avg_cadence {
+        let _ = writeln!(tcx, "        <Cadence>{}</Cadence>", avg_cadence);
+    }
+    let _ = writeln!(tcx, "        <TriggerMethod>{trigger}</TriggerMethod>");
+    // a track needs at least one trackpoint, workouts without samples have only the totals
+    if !records.is_empty() {
+        let _ = writeln!(tcx, "        <Track>");
+        for record in records {
+            write_trackpoint(tcx, record);
+        }
+        let _ = writeln!(tcx, "        </Track>");
+    }
+    if let (Some(avg_power), Some(max_power)) = (lap.avg_power, lap.max_power) {
+        let _ = writeln!(
+            tcx,
+            "        <Extensions><ns3:LX><ns3:AvgSpeed>{:.3}</ns3:AvgSpeed><ns3:AvgWatts>{}</ns3:AvgWatts><ns3:MaxWatts>{}</ns3:MaxWatts></ns3:LX></Extensions>",
+            lap.avg_speed, avg_power, max_power
+        );
+    }
+    let _ = writeln!(tcx, "      </Lap>");
+}
+
+fn write_trackpoint(tcx: &mut String, record: &Record) {
+    let _ = writeln!(tcx, "          <Trackpoint>");
+    let _ = writeln!(
+        tcx,
+        "            <Time>{}</Time>",
+        format_utc(record.timestamp)
+    );
+    if let Some((lat, lon)) = record.position {
+        let _ = writeln!(
+            tcx,
+            "            <Position><LatitudeDegrees>{lat:.6}</LatitudeDegrees><LongitudeDegrees>{lon:.6}</LongitudeDegrees></Position>"
+        );
+    }
+    if let Some(altitude) = record.altitude {
+        let _ = writeln!(
+            tcx,
+            "            <AltitudeMeters>{altitude:.1}</AltitudeMeters>"
+        );
+    }
+    let _ = writeln!(
+        tcx,
+        "            <DistanceMeters>{:.1}</DistanceMeters>",
+        record.distance
+    );
+    if let Some(heart_rate) = record.heart_rate {
+        let _ = writeln!(
+            tcx,
+            "            <HeartRateBpm><Value>{heart_rate}</Value></HeartRateBpm>"
+        );
+    }
+    if let Some(cadence) = record.cadence {
+        let _ = writeln!(tcx, "            <Cadence>{cadence}</Cadence>");
+    }
+    let watts = record
+        .power
+        .map(|power| format!("<ns3:Watts>{power}</ns3:Watts>"))
+        .unwrap_or_default();
+    let _ = writeln!(
+        tcx,
+        "            <Extensions><ns3:TPX><ns3:Speed>{:.3}</ns3:Speed>{watts}</ns3:TPX></Extensions>",
+        record.speed
+    );
+    let _ = writeln!(tcx, "          </Trackpoint>");
+}
+
+/// TCX only knows `Running`, `Biking` and `Other`
+fn tcx_sport(sport: &str) -> &'static str {
+    match sport {
+        "cycling" | "e_biking" => "Biking",
+        "running" => "Running",
+        _ => "Other",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{ride, temp_dir};
+    use crate::lap::AutoLap;
+
+    fn tcx(activity: &Activity, name: &str) -> String {
+        let path = temp_dir(name).join("ride.tcx");
+        write_tcx(activity, &path).unwrap();
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn a_ride_without_auto_lap_is_one_lap() {
+        let tcx = tcx(&ride(), "tcx-lap");
+        assert!(tcx.contains(r#"<Activity Sport="Biking">"#));
+        assert!(tcx.contains("<Id>2023-05-24T04:00:00Z</Id>"));
+        assert_eq!(tcx.matches("<Lap ").count(), 1);
+        assert!(tcx.contains("<TriggerMethod>Manual</TriggerMethod>"));
+        assert!(tcx.contains("<TotalTimeSeconds>3</TotalTimeSeconds>"));
+        assert!(tcx.contains("<DistanceMeters>15.0</DistanceMeters>"));
+        assert_eq!(tcx.matches("<Trackpoint>").count(), 4);
+        assert!(tcx.contains("<Notes>Morning ride</Notes>"));
+    }
+
+    #[test]
+    fn trackpoints_have_the_samples_and_their_extensions() {
+        let tcx = tcx(&ride(), "tcx-trackpoints");
+        assert!(tcx.contains(
+            "<Position><LatitudeDegrees>30.000200</LatitudeDegrees><LongitudeDegrees>120.000100</LongitudeDegrees></Position>"
+        ));
+        assert!(tcx.contains("<AltitudeMeters>11.5</AltitudeMeters>"));
+        assert!(tcx.contains("<HeartRateBpm><Value>142</Value></HeartRateBpm>"));
+        assert!(tcx.contains("<Cadence>86</Cadence>"));
+        assert!(tcx.contains(
+            "<Extensions><ns3:TPX><ns3:Speed>5.000</ns3:Speed><ns3:Watts>210</ns3:Watts></ns3:TPX></Extensions>"
+        ));
+        assert!(
+            tcx.contains(r#"xmlns:ns3="http://www.garmin.com/xmlschemas/ActivityExtension/v2""#)
+        );
+    }
+
+    #[test]
+    fn auto_laps_are_tcx_laps() {
+        let mut activity = ride();
+        activity.auto_lap = Some(AutoLap::Time(2));
+        let tcx = tcx(&activity, "tcx-auto-lap");
+        assert_eq!(tcx.matches("<Lap ").count(), 2);
+        assert!(tcx.contains("<TriggerMethod>Time</TriggerMethod>"));
+        assert!(tcx.contains(r#"<Lap StartTime="2023-05-24T04:00:02Z">"#));
+    }
+
+    #[test]
+    fn what_a_sample_did_not_measure_is_left_out() {
+        let mut activity = ride();
+        let record = &mut activity.records[1];
+        record.altitude = None;
+        record.heart_rate = None;
+        record.cadence = None;
+        record.power = None;
+        let tcx = tcx(&activity, "tcx-missing");
+        let trackpoints: Vec<_> = tcx.split("<Trackpoint>").skip(1).collect();
+        assert!(!trackpoints[1].contains("<AltitudeMeters>"));
+        assert!(!trackpoints[1].contains("<HeartRateBpm>"));
+        assert!(!trackpoints[1].contains("<Cadence>"));
+        assert!(trackpoints[1].contains("<ns3:TPX><ns3:Speed>5.000</ns3:Speed></ns3:TPX>"));
+        assert!(trackpoints[2].contains("<ns3:Watts>210</ns3:Watts>"));
+    }
+}